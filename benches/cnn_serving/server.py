@@ -17,6 +17,7 @@ from squeezenet import SqueezeNet
 
 import functionbench_pmem_local_pb2 as fbpml
 import functionbench_pmem_local_pb2_grpc as fbpml_grpc
+import clock
 
 
 session_conf = tf.ConfigProto(
@@ -69,6 +70,7 @@ class CNNServing(fbpml_grpc.OneArgumentServicer):
 def serve():
     server = grpc.server(futures.ThreadPoolExecutor(max_workers=1))
     fbpml_grpc.add_OneArgumentServicer_to_server(CNNServing(), server)
+    clock.add_to_server(server)
     server.add_insecure_port("[::]:50051")
     server.start()
     server.wait_for_termination()
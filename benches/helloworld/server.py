@@ -8,6 +8,7 @@ import grpc
 
 import functionbench_pmem_local_pb2 as fbpml
 import functionbench_pmem_local_pb2_grpc as fbpml_grpc
+import clock
 
 
 class Greeter(fbpml_grpc.ZeroArgumentsServicer):
@@ -23,6 +24,7 @@ class Greeter(fbpml_grpc.ZeroArgumentsServicer):
 def serve():
     server = grpc.server(futures.ThreadPoolExecutor(max_workers=1))
     fbpml_grpc.add_ZeroArgumentsServicer_to_server(Greeter(), server)
+    clock.add_to_server(server)
     server.add_insecure_port("[::]:50051")
     server.start()
     server.wait_for_termination()
@@ -11,6 +11,7 @@ import grpc
 
 import functionbench_pmem_local_pb2 as fbpml
 import functionbench_pmem_local_pb2_grpc as fbpml_grpc
+import clock
 
 from minio import Minio
 
@@ -55,6 +56,7 @@ class ImageRotate(fbpml_grpc.OneArgumentServicer):
 def serve():
     server = grpc.server(futures.ThreadPoolExecutor(max_workers=1))
     fbpml_grpc.add_OneArgumentServicer_to_server(ImageRotate(), server)
+    clock.add_to_server(server)
     server.add_insecure_port("[::]:50051")
     server.start()
     server.wait_for_termination()
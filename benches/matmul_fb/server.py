@@ -9,6 +9,7 @@ import grpc
 
 import functionbench_pmem_local_pb2 as fbpml
 import functionbench_pmem_local_pb2_grpc as fbpml_grpc
+import clock
 
 
 class FunctionBenchMatMul(fbpml_grpc.TwoArgumentsServicer):
@@ -38,6 +39,7 @@ def serve():
     fbpml_grpc.add_TwoArgumentsServicer_to_server(
         FunctionBenchMatMul(), server
     )
+    clock.add_to_server(server)
     server.add_insecure_port("[::]:50051")
     server.start()
     server.wait_for_termination()
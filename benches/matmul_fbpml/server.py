@@ -10,6 +10,7 @@ import grpc
 
 import functionbench_pmem_local_pb2 as fbpml
 import functionbench_pmem_local_pb2_grpc as fbpml_grpc
+import clock
 
 
 N = M = 512
@@ -33,6 +34,7 @@ class FbpmlMatMul(fbpml_grpc.ZeroArgumentsServicer):
 def serve():
     server = grpc.server(futures.ThreadPoolExecutor(max_workers=1))
     fbpml_grpc.add_ZeroArgumentsServicer_to_server(FbpmlMatMul(), server)
+    clock.add_to_server(server)
     server.add_insecure_port("[::]:50051")
     server.start()
     server.wait_for_termination()
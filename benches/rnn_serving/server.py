@@ -13,18 +13,16 @@ import grpc
 
 import functionbench_pmem_local_pb2 as fbpml
 import functionbench_pmem_local_pb2_grpc as fbpml_grpc
+import clock
 
 
 torch.set_num_threads(1)
 
 
-language1 = "Scottish"
-language2 = "Russian"
-start_letters1 = "ABCDEFGHIJKLMNOP"
-start_letters2 = "QRSTUVWXYZABCDEF"
-
-LANGUAGES = [language1, language2]
-START_LETTERS = [start_letters1, start_letters2]
+# The first start letter of the generated samples, for the languages that have
+# been used so far (any other language starts from 'A').
+START_LETTER_OFFSETS = {"Scottish": 0, "Russian": 16}
+DEFAULT_LENGTH = 16
 
 
 with open("/bench/rnn_params.pkl", "rb") as pkl:
@@ -68,18 +66,31 @@ rnn_model.load_state_dict(torch.load("/bench/rnn_model.pth"))
 rnn_model.eval()
 
 
-class RNNServing(fbpml_grpc.OneArgumentServicer):
+def start_letters(language: str, length: int) -> str:
+    offset = START_LETTER_OFFSETS.get(language, 0)
+    return "".join(
+        string.ascii_uppercase[(offset + i) % len(string.ascii_uppercase)]
+        for i in range(length)
+    )
+
+
+class RNNServing(fbpml_grpc.StringArgumentServicer):
     def Bench(
-        self, request: fbpml.OneArgumentRequest, context: grpc.ServicerContext
+        self, request: fbpml.StringArgumentRequest, context: grpc.ServicerContext
     ):
         response_start = time.time()
         response_duration = Duration()
 
-        idx = request.arg if request.arg and 0 <= request.arg <= 1 else 0
-        language = LANGUAGES[idx]
-        start_letters = START_LETTERS[idx]
+        if request.arg not in all_categories:
+            context.abort(
+                grpc.StatusCode.INVALID_ARGUMENT,
+                f"unknown language '{request.arg}'",
+            )
+        length = request.len if request.len > 0 else DEFAULT_LENGTH
 
-        _ = list(rnn_model.samples(language, start_letters))
+        _ = list(
+            rnn_model.samples(request.arg, start_letters(request.arg, length))
+        )
 
         response_duration.FromTimedelta(
             dt.timedelta(seconds=time.time() - response_start)
@@ -89,7 +100,8 @@ class RNNServing(fbpml_grpc.OneArgumentServicer):
 
 def serve():
     server = grpc.server(futures.ThreadPoolExecutor(max_workers=1))
-    fbpml_grpc.add_OneArgumentServicer_to_server(RNNServing(), server)
+    fbpml_grpc.add_StringArgumentServicer_to_server(RNNServing(), server)
+    clock.add_to_server(server)
     server.add_insecure_port("[::]:50051")
     server.start()
     server.wait_for_termination()
@@ -1,4 +1,5 @@
 use std::{
+    io,
     os::unix::prelude::FileTypeExt,
     path::{Path, PathBuf},
     time::Duration,
@@ -7,11 +8,14 @@ use std::{
 use anyhow::{bail, Context, Result};
 use clap::{Parser, Subcommand};
 
-use hyper::{Client, StatusCode};
-use hyperlocal::{UnixClientExt, Uri};
 use tokio::time::Instant;
 
-use fbpml::{one_arg_rpc, two_args_rpc, zero_args_rpc, Measurement};
+use fbpml::{
+    firecracker::SnapshotLoadParams,
+    manifest::Manifest,
+    output::{MeasurementWriter, OutputFormat},
+    BenchClient, BenchCmd, Error, FirecrackerApi, Measurement, RpcTiming, TimeUnit,
+};
 use fbpml_rpc::ServiceResponse;
 
 /// A CLI for the gRPC clients of the benchmarks supported  in fbpml.
@@ -24,6 +28,23 @@ struct Cli {
     #[clap(short = 'c', long = "server-addr")]
     address_port: String,
 
+    /// The format in which the resulting measurements are printed to stdout; one of 'csv',
+    /// 'csv-header', 'json' or 'jsonl'.
+    #[clap(short = 'o', long = "output-format", default_value = "csv")]
+    output_format: OutputFormat,
+
+    /// The unit of the durations in CSV output; one of 'ns', 'us', 'ms' or 's' (fractional
+    /// seconds, with nanosecond precision). JSON output always retains full precision.
+    #[clap(short = 'u', long = "time-unit", default_value = "us")]
+    time_unit: TimeUnit,
+
+    /// Establish a single connection to the gRPC server (right before the "cold" request) and
+    /// reuse it for all subsequent requests, rather than reconnecting for each one of them. When
+    /// restoring, the connection for the "cold" request is always established (and timed)
+    /// separately; this only controls whether it is reused afterwards.
+    #[clap(long = "reuse-connection")]
+    reuse_connection: bool,
+
     #[clap(subcommand)]
     top_cmd: TopSubcommand,
 }
@@ -55,81 +76,51 @@ struct RestoreCmd {
     #[clap(short = 'm', long)]
     memory_file: PathBuf,
 
+    /// Have Firecracker track the pages that the restored MicroVM dirties, so that it can be diff
+    /// snapshotted in turn. It is not needed to restore from the diff snapshots created by
+    /// `build-snapshots --diff-snapshots`, whose memory files have already been layered on top of
+    /// their base, and it adds the overhead of tracking to the restored MicroVM.
+    #[clap(long = "enable-diff-snapshots")]
+    enable_diff_snapshots: bool,
+
+    /// Refuse to restore from a snapshot of a benchmark that fetches its input from MinIO once it
+    /// is older than the clock skew that MinIO tolerates (15min), as per its manifest; such stale
+    /// snapshots are only warned about otherwise.
+    #[clap(long = "refuse-stale", conflicts_with = "sync-clock")]
+    refuse_stale: bool,
+
+    /// Set the guest's clock to the host's right after resuming the MicroVM (through the `Clock`
+    /// service that all benchmarks serve), so that snapshots of any age can be restored. It is
+    /// issued over the connection of the "cold" request, once it has been established (and timed
+    /// as `connect`), and is itself timed separately (i.e., the `sync_clock` and `clock_skew`
+    /// columns) and excluded from the global duration.
+    #[clap(long = "sync-clock")]
+    sync_clock: bool,
+
     #[clap(subcommand)]
     bench: BenchCmd,
 }
 
-#[derive(Subcommand)]
-enum BenchCmd {
-    /// Run the `chameleon` benchmark.
-    Chameleon { arg1: u64, arg2: u64 },
-
-    /// Run the `cnn_serving` benchmark.
-    CNNServing { arg: u64 },
-
-    /// Run the `helloworld` benchmark.
-    #[clap(name = "helloworld")]
-    HelloWorld,
-
-    /// Run the `image_rotate` benchmark.
-    ImageRotate { arg: u64 },
-
-    /// Run the `json_serdes` benchmark.
-    JSONSerdes { arg: u64 },
-
-    /// Run the `lr_serving` benchmark.
-    LRServing { arg: u64 },
-
-    /// Run the `lr_training` benchmark.
-    LRTraining { arg: u64 },
-
-    /// Run the `matmul_fb` benchmark.
-    #[clap(name = "matmul-fb")]
-    MatMulFb { arg1: u64, arg2: u64 },
-
-    /// Run the `matmul_fbpml` benchmark.
-    #[clap(name = "matmul-fbpml")]
-    MatMulFbpml,
-
-    /// Run the `pyaes` benchmark.
-    #[clap(name = "pyaes")]
-    PyAES,
-
-    /// Run the `rnn_serving` benchmark.
-    RNNServing { arg: u64 },
-
-    /// Run the `video_processing` benchmark.
-    VideoProcessing { arg: u64 },
+/// Issue the benchmark's gRPC request through `client`.
+async fn issue(bcmd: &BenchCmd, client: &BenchClient) -> Result<(Duration, ServiceResponse)> {
+    let args = bcmd.rpc_args();
+    client
+        .bench(args)
+        .await
+        .with_context(|| format!("could not issue the {} request", args.name()))
 }
 
-impl BenchCmd {
-    async fn issue(&self, address_port: String) -> Result<(Duration, ServiceResponse)> {
-        use crate::BenchCmd::*;
-        Ok(match self {
-            HelloWorld | MatMulFbpml | PyAES => zero_args_rpc(address_port)
-                .await
-                .with_context(|| "could not issue the 'cold' zero-arguments request")?,
-
-            CNNServing { arg }
-            | ImageRotate { arg }
-            | JSONSerdes { arg }
-            | LRServing { arg }
-            | LRTraining { arg }
-            | VideoProcessing { arg } => one_arg_rpc(address_port, *arg)
-                .await
-                .with_context(|| "could not issue the 'cold' one-argument request")?,
-
-            Chameleon { arg1, arg2 } | MatMulFb { arg1, arg2 } => {
-                two_args_rpc(address_port, *arg1, *arg2)
-                    .await
-                    .with_context(|| "could not issue the 'cold' two-arguments request")?
-            }
-
-            RNNServing { arg: _ } => {
-                bail!("Benchmark 'rnn_serving' is not implemented yet")
-            }
-        })
-    }
+/// Issue the benchmark's gRPC request through `client`, also timing the first byte of the
+/// response.
+async fn issue_timed(
+    bcmd: &BenchCmd,
+    client: &BenchClient,
+) -> Result<(RpcTiming, ServiceResponse)> {
+    let args = bcmd.rpc_args();
+    client
+        .bench_timed(args)
+        .await
+        .with_context(|| format!("could not issue the {} request", args.name()))
 }
 
 impl RestoreCmd {
@@ -159,85 +150,56 @@ impl RestoreCmd {
         validate_file(&self.state_file)?;
         validate_file(&self.memory_file)?;
 
+        // Make sure the snapshot was built for the given benchmark and is still usable, if it has
+        // a manifest (its age does not matter if the guest's clock is going to be set anyway)
+        let bench = self.bench.bench();
+        if let Some(entry) = Manifest::load_and_check(bench, &self.state_file, &self.memory_file)? {
+            if !self.sync_clock {
+                if let Err(err) = entry.check_fresh(bench, &self.state_file) {
+                    if self.refuse_stale {
+                        return Err(err.into());
+                    }
+                    eprintln!("WARNING: {err}");
+                }
+            }
+        }
+
         Ok(())
     }
 
     async fn restore(&self) -> Result<Duration> {
-        let restore_body = format!(
-            "{{\"snapshot_path\":\"{}\",\"mem_file_path\":\"{}\",\"enable_diff_snapshots\":false,\"resume_vm\":false}}",
-            self.state_file.display(),
-            self.memory_file.display()
-        );
-
-        let client = Client::unix();
-        let uri = Uri::new(self.api_sock_path.as_path(), "/snapshot/load");
-        let req = hyper::Request::put(uri)
-            .header("Accept", "application/json")
-            .header("Content-Type", "application/json")
-            .body(restore_body.into())
+        let params = SnapshotLoadParams {
+            snapshot_path: self.state_file.clone(),
+            mem_file_path: self.memory_file.clone(),
+            enable_diff_snapshots: self.enable_diff_snapshots,
+            resume_vm: false,
+        };
+
+        FirecrackerApi::new(&self.api_sock_path)
+            .load_snapshot(&params)
+            .await
+            .map_err(Error::SnapshotLoad)
             .with_context(|| {
                 format!(
-                    "failed to construct HTTP PUT request for {:?} to restore the MicroVM",
-                    Uri::new(self.api_sock_path.as_path(), "/snapshot/load")
+                    "Could not restore the MicroVM behind '{}' from '{}' and '{}'",
+                    self.api_sock_path.display(),
+                    self.state_file.display(),
+                    self.memory_file.display(),
                 )
-            })?;
-
-        let restore_start = Instant::now();
-        let resp = client.request(req).await.with_context(|| {
-            format!(
-                "could not  HTTP PUT /snapshot/load  @ '{}'",
-                self.api_sock_path.display()
-            )
-        })?;
-        let restore_end = Instant::now();
-
-        if resp.status() != StatusCode::NO_CONTENT {
-            bail!(
-                "Could not restore the MicroVM behind '{}' from '{}' and '{}': {}",
-                self.api_sock_path.display(),
-                self.state_file.display(),
-                self.memory_file.display(),
-                resp.status()
-            );
-        }
-
-        Ok(restore_end - restore_start)
+            })
     }
 
     async fn resume(&self) -> Result<Duration> {
-        const RESUME_BODY: &str = r#"{"state":"Resumed"}"#;
-
-        let client = Client::unix();
-        let uri = Uri::new(self.api_sock_path.as_path(), "/vm");
-        let req = hyper::Request::patch(uri)
-            .header("Accept", "application/json")
-            .header("Content-Type", "application/json")
-            .body(RESUME_BODY.into())
+        FirecrackerApi::new(&self.api_sock_path)
+            .resume()
+            .await
+            .map_err(Error::Resume)
             .with_context(|| {
                 format!(
-                    "failed to construct HTTP PATCH request for {:?} to resume the MicroVM",
-                    Uri::new(self.api_sock_path.as_path(), "/vm")
+                    "Could not resume the MicroVM behind '{}'",
+                    self.api_sock_path.display()
                 )
-            })?;
-
-        let resume_start = Instant::now();
-        let resp = client.request(req).await.with_context(|| {
-            format!(
-                "could not  HTTP PATCH /vm  @ '{}'",
-                self.api_sock_path.display()
-            )
-        })?;
-        let resume_end = Instant::now();
-
-        if resp.status() != StatusCode::NO_CONTENT {
-            bail!(
-                "Could not resume the MicroVM behind '{}': {}",
-                self.api_sock_path.display(),
-                resp.status()
-            );
-        }
-
-        Ok(resume_end - resume_start)
+            })
     }
 }
 
@@ -245,20 +207,24 @@ impl RestoreCmd {
 async fn main() -> Result<()> {
     let mut cli = Cli::parse();
 
-    // Prepend scheme to ADDR:PORT, to be ready for use in a URL.
+    // Prepend scheme to ADDR:PORT, to be ready for use in a URL, and construct the gRPC client
+    // here, to keep the parsing of the address out of the global timer.
     cli.address_port.insert_str(0, "http://");
-    // Also clone it here, to keep the extra allocation out of the global timer.
-    let addr_port = cli.address_port.clone();
+    let mut client = BenchClient::new(cli.address_port.clone())
+        .with_context(|| format!("invalid server address '{}'", cli.address_port))?;
 
     let m: Measurement = match &cli.top_cmd {
         TopSubcommand::Issue(bench_cmd) => {
             let global_start = Instant::now();
-            let cold = bench_cmd.issue(addr_port).await?;
+            if cli.reuse_connection {
+                client.connect().await?;
+            }
+            let cold = issue(bench_cmd, &client).await?;
             let global_delay = Instant::now() - global_start;
 
-            let warm = bench_cmd.issue(cli.address_port).await?;
+            let warm = issue(bench_cmd, &client).await?;
 
-            (global_delay, cold.into(), warm.into()).into()
+            (global_delay, cold.try_into()?, warm.try_into()?).into()
         }
 
         TopSubcommand::Restore(rcmd) => {
@@ -268,15 +234,47 @@ async fn main() -> Result<()> {
             let global_start = Instant::now();
             let restore = rcmd.restore().await?;
             let resume = rcmd.resume().await?;
-            let cold = rcmd.bench.issue(addr_port).await?;
-            let global = Instant::now() - global_start;
-
-            let warm = rcmd.bench.issue(cli.address_port).await?;
-
-            (global, restore, resume, cold.into(), warm.into()).into()
+            let connect = client.connect().await?;
+            // Set the guest's clock over the same connection, if requested, excluding it from the
+            // global timer
+            let (clock, clock_elapsed) = if rcmd.sync_clock {
+                let start = Instant::now();
+                let clock = client
+                    .sync_clock()
+                    .await
+                    .with_context(|| "could not set the guest's clock")?;
+                (Some(clock), Instant::now() - start)
+            } else {
+                (None, Duration::ZERO)
+            };
+            let (cold_timing, cold) = issue_timed(&rcmd.bench, &client).await?;
+            let global = Instant::now() - global_start - clock_elapsed;
+
+            if !cli.reuse_connection {
+                client.disconnect();
+            }
+            let warm = issue(&rcmd.bench, &client).await?;
+
+            let m: Measurement = (
+                global,
+                restore,
+                resume,
+                (cold_timing.total, cold).try_into()?,
+                warm.try_into()?,
+            )
+                .into();
+            m.with_connection(connect, cold_timing.first_byte)
+                .with_clock(clock)
         }
     };
 
-    println!("{m}");
+    let sync_clock = matches!(&cli.top_cmd, TopSubcommand::Restore(rcmd) if rcmd.sync_clock);
+    let mut out = MeasurementWriter::new(io::stdout().lock(), cli.output_format, false)
+        .time_unit(cli.time_unit)
+        .sync_clock(sync_clock);
+    out.write(0, &m)
+        .with_context(|| "failed to write the measurement to stdout")?;
+    out.finish()
+        .with_context(|| "failed to write the measurement to stdout")?;
     Ok(())
 }
@@ -1,21 +1,37 @@
 use std::{
-    mem::MaybeUninit, net::ToSocketAddrs, os::unix::prelude::FileTypeExt, path::PathBuf,
-    string::ToString, sync::Arc, time::Duration,
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter},
+    net::ToSocketAddrs,
+    os::unix::prelude::FileTypeExt,
+    path::{Path, PathBuf},
+    string::ToString,
+    sync::Arc,
+    time::Duration,
 };
 
 use anyhow::{bail, Context, Result};
 use clap::{Parser, Subcommand};
 
 use futures::future::try_join_all;
-use hyper::{Client, StatusCode};
-use hyperlocal::{UnixClientExt, Uri};
-use rand::{prelude::StdRng, Rng, SeedableRng};
-use tokio::{
-    sync::Barrier,
-    time::{sleep, Instant},
+use tokio::{sync::Barrier, time::Instant};
+
+use fbpml::{
+    cache::{ResidencyReport, SnapshotFiles},
+    firecracker::SnapshotLoadParams,
+    load::{open_loop, Arrivals, LoadOpts, LoadReport},
+    manifest::Manifest,
+    multi::{
+        pre_warm, restore_and_bench, restore_and_bench_probed, Arrival, Rendezvous,
+        RestoreArrivals, RestoreSchedule, RestoreTarget, WorkerOpts, RESTORE_RENDEZVOUS,
+    },
+    output::{
+        InvocationWriter, LoadWriter, MeasurementWriter, OutputFormat, TimelineWriter, TraceWriter,
+    },
+    replay::{replay, ReplayOpts, ReplayUvm, Start, Trace},
+    timeline::{Epoch, MemorySample, MemorySampler, Phase, Timeline},
+    BenchClient, BenchCmd, Error, FirecrackerApi, Measurement, TimeUnit,
 };
-
-use fbpml::{one_arg_rpc, two_args_rpc, zero_args_rpc, Measurement};
 use fbpml_rpc::ServiceResponse;
 
 /// A CLI for the gRPC clients of the benchmarks supported in fbpml.
@@ -38,11 +54,67 @@ struct Cli {
     #[clap(short = 'w', long = "pre-warm", required = false, default_value = "0")]
     pre_warm: usize,
 
+    /// The format in which the resulting measurements are printed to stdout; one of 'csv',
+    /// 'csv-header', 'json' or 'jsonl'.
+    #[clap(short = 'o', long = "output-format", default_value = "csv")]
+    output_format: OutputFormat,
+
+    /// The unit of the durations in CSV output; one of 'ns', 'us', 'ms' or 's' (fractional
+    /// seconds, with nanosecond precision). JSON output always retains full precision.
+    #[clap(short = 'u', long = "time-unit", default_value = "us")]
+    time_unit: TimeUnit,
+
+    /// Establish a single connection to the gRPC server (right before the "cold" request) and
+    /// reuse it for all subsequent requests, rather than reconnecting for each one of them. When
+    /// restoring, the connection for the "cold" request is always established (and timed)
+    /// separately; this only controls whether it is reused afterwards.
+    #[clap(long = "reuse-connection")]
+    reuse_connection: bool,
+
+    /// Report how many pages of each MicroVM's snapshot (and rootfs) files resided in the page
+    /// cache right before it was restored and after the "warm" request had been served, next to
+    /// its measurements. Only applies to the `restore` subcommand.
+    #[clap(long = "residency-report")]
+    residency_report: bool,
+
+    /// Abandon any request (or connection attempt) that takes longer than the given (fractional)
+    /// number of seconds, reporting the MicroVM as failed with a 'timeout' error.
+    /// Does not apply to the `replay` subcommand.
+    #[clap(long = "timeout", parse(try_from_str = parse_secs))]
+    timeout: Option<Duration>,
+
+    /// Also write when each phase (i.e., restore, resume, sync-clock, connect, cold, pre-warm and
+    /// warm) of each MicroVM began and ended, relative to a common epoch, to the given file, in the
+    /// same `--output-format` and `--time-unit`. Only applies to the `issue` and `restore`
+    /// subcommands.
+    #[clap(long = "timeline")]
+    timeline: Option<PathBuf>,
+
+    /// Also write the timelines of all MicroVMs (see `--timeline`) to the given file in the Chrome
+    /// trace-event format, with one track per MicroVM, to be loaded in Perfetto. Only applies to
+    /// the `issue` and `restore` subcommands.
+    #[clap(long = "trace-out")]
+    trace_out: Option<PathBuf>,
+
+    /// Sample the host's memory usage every given (fractional) number of seconds throughout the
+    /// run, and include it in the `--trace-out` file as counter tracks.
+    #[clap(long = "trace-memory-interval", requires = "trace-out", parse(try_from_str = parse_secs))]
+    trace_memory_interval: Option<Duration>,
+
     #[clap(subcommand)]
     top_cmd: TopSubcommand,
 }
 
 impl Cli {
+    fn worker_opts(&self) -> WorkerOpts {
+        WorkerOpts {
+            pre_warm: self.pre_warm,
+            reuse_connection: self.reuse_connection,
+            sync_clock: false,
+            timeout: self.timeout,
+        }
+    }
+
     /// Parse the given `IP_ADDRESS:PORT` format, make sure it is valid, and return a `Vec<String>`
     /// that contains the addresses of all MicroVMs (their number must have been given as an
     /// argument as well) in the expected `IP_ADDRESS:PORT` format.
@@ -78,97 +150,193 @@ enum TopSubcommand {
     /// being provided) before issuing the two gRPC requests (a cold and a
     /// warm) to it.
     Restore(RestoreCmd),
+
+    /// Issue gRPC requests to already-running MicroVMs at a target rate (i.e., open-loop) for a
+    /// given duration, after a "cold" (and any pre-warm) request, reporting the percentiles of
+    /// their latencies for each MicroVM and for all of them together.
+    Load(LoadCmd),
+
+    /// Replay an invocation trace, spawning a Firecracker process and restoring a MicroVM from
+    /// the snapshot of the invoked function whenever no warm MicroVM can serve an invocation, and
+    /// reporting the outcome of each invocation.
+    Replay(ReplayCmd),
 }
 
 #[derive(clap::Args, Clone)]
-struct RestoreCmd {
-    /// Path to the Unix domain socket of the Firecracker instance to be restored.
-    #[clap(short = 'x', long = "api-sock")]
-    api_sock_path: PathBuf,
+struct LoadCmd {
+    /// The (mean) number of requests to issue per second to each MicroVM.
+    #[clap(short = 'r', long = "rate")]
+    rate: f64,
 
-    /// Path to the state file of the MicroVM snapshot to be restored.
-    #[clap(short = 's', long)]
-    state_file: PathBuf,
+    /// For how long to keep issuing requests, in (fractional) seconds.
+    #[clap(short = 'd', long = "duration", parse(try_from_str = parse_secs))]
+    duration: Duration,
 
-    /// Path to the memory file of the MicroVM snapshot to be restored.
-    #[clap(short = 'm', long)]
-    memory_file: PathBuf,
+    /// How the arrival times of the requests are spaced; one of 'constant' or 'poisson'.
+    #[clap(short = 'a', long = "arrivals", default_value = "constant")]
+    arrivals: Arrivals,
 
     #[clap(subcommand)]
     bench: BenchCmd,
 }
 
-#[derive(Subcommand, Clone, Copy)]
-enum BenchCmd {
-    /// Run the `chameleon` benchmark.
-    Chameleon { arg1: u64, arg2: u64 },
-
-    /// Run the `cnn_serving` benchmark.
-    CNNServing { arg: u64 },
-
-    /// Run the `helloworld` benchmark.
-    #[clap(name = "helloworld")]
-    HelloWorld,
-
-    /// Run the `image_rotate` benchmark.
-    ImageRotate { arg: u64 },
+impl LoadCmd {
+    fn opts(&self) -> Result<LoadOpts> {
+        if !(self.rate.is_finite() && self.rate > 0.0) {
+            bail!("the rate must be a positive number of requests per second");
+        }
+        Ok(LoadOpts {
+            rate: self.rate,
+            duration: self.duration,
+            arrivals: self.arrivals,
+        })
+    }
+}
 
-    /// Run the `json_serdes` benchmark.
-    JSONSerdes { arg: u64 },
+#[derive(clap::Args, Clone)]
+struct ReplayCmd {
+    /// Path to the trace to replay; a CSV file of `timestamp,function,args` lines, where
+    /// `timestamp` is in (fractional) seconds since the beginning of the trace and `args` are
+    /// separated by whitespace.
+    #[clap(short = 't', long = "trace")]
+    trace: PathBuf,
+
+    /// For how long (in seconds) an idle MicroVM is kept alive; its next invocation is a cold
+    /// start after that.
+    #[clap(short = 'k', long = "keep-alive", default_value = "600", parse(try_from_str = parse_secs))]
+    keep_alive: Duration,
+
+    /// Path to the firecracker binary.
+    #[clap(long = "fc-bin")]
+    fc_bin: PathBuf,
+
+    /// Path format of the Unix domain sockets of the Firecracker instances, where the `IDh`
+    /// substring is replaced by each MicroVM's ID.
+    #[clap(
+        short = 'x',
+        long = "api-sock",
+        default_value = "/tmp/firecracker-replay-IDh.socket"
+    )]
+    api_sock_fmt: String,
+
+    /// Path format of the state files of the snapshots, where the `BENCH` substring is replaced by
+    /// the name of each function and the `IDh` substring by each MicroVM's ID.
+    #[clap(short = 's', long = "state-file")]
+    state_file_fmt: String,
+
+    /// Path format of the memory files of the snapshots; see `--state-file`.
+    #[clap(short = 'm', long = "memory-file")]
+    memory_file_fmt: String,
+}
 
-    /// Run the `lr_serving` benchmark.
-    LRServing { arg: u64 },
+impl ReplayCmd {
+    /// The pool of MicroVMs at `addrs`, along with their snapshots for each one of the functions of
+    /// `trace`, all of which must be present.
+    fn uvms(&self, trace: &Trace, addrs: Vec<String>) -> Result<Vec<ReplayUvm>> {
+        let benches = trace.benchmarks();
+        addrs
+            .into_iter()
+            .enumerate()
+            .map(|(id, addr)| {
+                let hex_id = format!("{id:02X}");
+                let mut snapshots = HashMap::new();
+                for bench in &benches {
+                    let path = |fmt: &str| {
+                        let path =
+                            PathBuf::from(fmt.replace("BENCH", bench.name).replace("IDh", &hex_id));
+                        if !path
+                            .metadata()
+                            .with_context(|| format!("could not stat(2) '{}'", path.display()))?
+                            .is_file()
+                        {
+                            bail!("'{}' should point to a plain (binary) file", path.display());
+                        }
+                        Ok(path)
+                    };
+                    let snapshot = SnapshotLoadParams {
+                        snapshot_path: path(&self.state_file_fmt)?,
+                        mem_file_path: path(&self.memory_file_fmt)?,
+                        enable_diff_snapshots: false,
+                        resume_vm: false,
+                    };
+                    snapshots.insert(bench.name, snapshot);
+                }
+                Ok(ReplayUvm {
+                    id,
+                    addr,
+                    api_sock: PathBuf::from(self.api_sock_fmt.replace("IDh", &hex_id)),
+                    snapshots,
+                })
+            })
+            .collect()
+    }
+}
 
-    /// Run the `lr_training` benchmark.
-    LRTraining { arg: u64 },
+fn parse_secs(s: &str) -> Result<Duration> {
+    let secs: f64 = s
+        .parse()
+        .with_context(|| format!("invalid duration '{s}'"))?;
+    Duration::try_from_secs_f64(secs).with_context(|| format!("invalid duration '{s}'"))
+}
 
-    /// Run the `matmul_fb` benchmark.
-    #[clap(name = "matmul-fb")]
-    MatMulFb { arg1: u64, arg2: u64 },
+#[derive(clap::Args, Clone)]
+struct RestoreCmd {
+    /// Path to the Unix domain socket of the Firecracker instance to be restored.
+    #[clap(short = 'x', long = "api-sock")]
+    api_sock_path: PathBuf,
 
-    /// Run the `matmul_fbpml` benchmark.
-    #[clap(name = "matmul-fbpml")]
-    MatMulFbpml,
+    /// Path to the state file of the MicroVM snapshot to be restored.
+    #[clap(short = 's', long)]
+    state_file: PathBuf,
 
-    /// Run the `pyaes` benchmark.
-    #[clap(name = "pyaes")]
-    PyAES,
+    /// Path to the memory file of the MicroVM snapshot to be restored.
+    #[clap(short = 'm', long)]
+    memory_file: PathBuf,
 
-    /// Run the `rnn_serving` benchmark.
-    RNNServing { arg: u64 },
+    /// Path to the rootfs image of the MicroVM, to be included in the `--residency-report`.
+    #[clap(short = 'r', long)]
+    rootfs_file: Option<PathBuf>,
+
+    /// Have Firecracker track the pages that the restored MicroVM dirties, so that it can be diff
+    /// snapshotted in turn. It is not needed to restore from the diff snapshots created by
+    /// `build-snapshots --diff-snapshots`, whose memory files have already been layered on top of
+    /// their base, and it adds the overhead of tracking to the restored MicroVM.
+    #[clap(long = "enable-diff-snapshots")]
+    enable_diff_snapshots: bool,
+
+    /// Refuse to restore from a snapshot of a benchmark that fetches its input from MinIO once it
+    /// is older than the clock skew that MinIO tolerates (15min), as per its manifest; such stale
+    /// snapshots are only warned about otherwise.
+    #[clap(long = "refuse-stale", conflicts_with = "sync-clock")]
+    refuse_stale: bool,
+
+    /// Set the guest's clock to the host's right after resuming each MicroVM (through the `Clock`
+    /// service that all benchmarks serve), so that snapshots of any age can be restored. It is
+    /// issued over the connection of the "cold" request, once it has been established (and timed
+    /// as `connect`), and is itself timed separately (i.e., the `sync_clock` and `clock_skew`
+    /// columns) and excluded from the global duration.
+    #[clap(long = "sync-clock")]
+    sync_clock: bool,
+
+    /// How the restores of the MicroVMs are spread in time; one of 'simultaneous', 'stagger:MILLIS'
+    /// (i.e., a fixed interval between consecutive MicroVMs), 'poisson:RATE' (i.e., a mean number
+    /// of restores per second) or 'concurrent:K' (i.e., at most K MicroVMs being restored at any
+    /// time). Unless 'simultaneous', the `arrival` and `start` of each MicroVM are reported
+    /// relative to a common epoch, as additional columns.
+    #[clap(short = 'a', long = "arrivals", default_value = "simultaneous")]
+    arrivals: RestoreArrivals,
 
-    /// Run the `video_processing` benchmark.
-    VideoProcessing { arg: u64 },
+    #[clap(subcommand)]
+    bench: BenchCmd,
 }
 
-impl BenchCmd {
-    async fn issue(&self, address_port: String) -> Result<(Duration, ServiceResponse)> {
-        use crate::BenchCmd::*;
-        Ok(match self {
-            HelloWorld | MatMulFbpml | PyAES => zero_args_rpc(address_port)
-                .await
-                .with_context(|| "could not issue the 'cold' zero-arguments request")?,
-
-            CNNServing { arg }
-            | ImageRotate { arg }
-            | JSONSerdes { arg }
-            | LRServing { arg }
-            | LRTraining { arg }
-            | VideoProcessing { arg } => one_arg_rpc(address_port, *arg)
-                .await
-                .with_context(|| "could not issue the 'cold' one-argument request")?,
-
-            Chameleon { arg1, arg2 } | MatMulFb { arg1, arg2 } => {
-                two_args_rpc(address_port, *arg1, *arg2)
-                    .await
-                    .with_context(|| "could not issue the 'cold' two-arguments request")?
-            }
-
-            RNNServing { arg: _ } => {
-                bail!("Benchmark 'rnn_serving' is not implemented yet")
-            }
-        })
-    }
+/// Issue the benchmark's gRPC request through `client`.
+async fn issue(bcmd: &BenchCmd, client: &BenchClient) -> Result<(Duration, ServiceResponse)> {
+    let args = bcmd.rpc_args();
+    client
+        .bench(args)
+        .await
+        .with_context(|| format!("could not issue the {} request", args.name()))
 }
 
 impl RestoreCmd {
@@ -219,176 +387,174 @@ impl RestoreCmd {
         };
         validate_file(&mut self.state_file)?;
         validate_file(&mut self.memory_file)?;
+        if let Some(rootfs_file) = &mut self.rootfs_file {
+            validate_file(rootfs_file)?;
+        }
+
+        // Make sure the snapshot was built for the given benchmark and is still usable, if it has
+        // a manifest (its age does not matter if the guest's clock is going to be set anyway)
+        let bench = self.bench.bench();
+        if let Some(entry) = Manifest::load_and_check(bench, &self.state_file, &self.memory_file)? {
+            if !self.sync_clock {
+                if let Err(err) = entry.check_fresh(bench, &self.state_file) {
+                    if self.refuse_stale {
+                        return Err(err.into());
+                    }
+                    eprintln!("WARNING: {err}");
+                }
+            }
+        }
 
         Ok(())
     }
 
-    async fn restore(&self) -> Result<Duration> {
-        let restore_body = format!(
-            "{{\"snapshot_path\":\"{}\",\"mem_file_path\":\"{}\",\"enable_diff_snapshots\":false,\"resume_vm\":false}}",
-            self.state_file.display(),
-            self.memory_file.display()
-        );
-
-        let client = Client::unix();
-        let uri = Uri::new(self.api_sock_path.as_path(), "/snapshot/load");
-        let req = hyper::Request::put(uri)
-            .header("Accept", "application/json")
-            .header("Content-Type", "application/json")
-            .body(restore_body.into())
-            .with_context(|| {
-                format!(
-                    "failed to construct HTTP PUT request for {:?} to restore the MicroVM",
-                    Uri::new(self.api_sock_path.as_path(), "/snapshot/load")
-                )
-            })?;
-
-        let restore_start = Instant::now();
-        let resp = client.request(req).await.with_context(|| {
-            format!(
-                "could not  HTTP PUT /snapshot/load  @ '{}'",
-                self.api_sock_path.display()
-            )
-        })?;
-        let restore_end = Instant::now();
-
-        if resp.status() != StatusCode::NO_CONTENT {
-            bail!(
-                "Could not restore the MicroVM behind '{}' from '{}' and '{}': {}",
-                self.api_sock_path.display(),
-                self.state_file.display(),
-                self.memory_file.display(),
-                resp.status()
-            );
+    /// The MicroVM to be restored, along with its snapshot; see [`RestoreCmd::validate`].
+    fn target(&self) -> RestoreTarget {
+        RestoreTarget {
+            api: FirecrackerApi::new(&self.api_sock_path),
+            snapshot: SnapshotLoadParams {
+                snapshot_path: self.state_file.clone(),
+                mem_file_path: self.memory_file.clone(),
+                enable_diff_snapshots: self.enable_diff_snapshots,
+                resume_vm: false,
+            },
         }
-
-        Ok(restore_end - restore_start)
     }
 
-    async fn resume(&self) -> Result<Duration> {
-        const RESUME_BODY: &str = r#"{"state":"Resumed"}"#;
-
-        let client = Client::unix();
-        let uri = Uri::new(self.api_sock_path.as_path(), "/vm");
-        let req = hyper::Request::patch(uri)
-            .header("Accept", "application/json")
-            .header("Content-Type", "application/json")
-            .body(RESUME_BODY.into())
-            .with_context(|| {
-                format!(
-                    "failed to construct HTTP PATCH request for {:?} to resume the MicroVM",
-                    Uri::new(self.api_sock_path.as_path(), "/vm")
-                )
-            })?;
-
-        let resume_start = Instant::now();
-        let resp = client.request(req).await.with_context(|| {
-            format!(
-                "could not  HTTP PATCH /vm  @ '{}'",
-                self.api_sock_path.display()
-            )
-        })?;
-        let resume_end = Instant::now();
-
-        if resp.status() != StatusCode::NO_CONTENT {
-            bail!(
-                "Could not resume the MicroVM behind '{}': {}",
-                self.api_sock_path.display(),
-                resp.status()
-            );
+    /// The files backing the MicroVM to be restored; see [`RestoreCmd::validate`].
+    fn files(&self) -> SnapshotFiles {
+        SnapshotFiles {
+            state: self.state_file.clone(),
+            memory: self.memory_file.clone(),
+            rootfs: self.rootfs_file.clone(),
         }
-
-        Ok(resume_end - resume_start)
     }
 }
 
+/// The number of times [`task_issue`] waits on the shared [`Barrier`].
+const ISSUE_RENDEZVOUS: usize = 4;
+
 /// A standalone worker task's routine in case the `issue` subcommand has been provided.
 async fn task_issue(
     id: usize,
     address_port: String,
     bcmd: &BenchCmd,
-    num_prewarm: usize,
-    barrier: Arc<Barrier>,
-) -> Result<(usize, Measurement)> {
+    opts: WorkerOpts,
+    epoch: Epoch,
+    barrier: &mut Rendezvous,
+) -> Result<Measurement> {
     // Allocations (before the timer begins)
-    let addr_port = address_port.clone();
+    let mut client = BenchClient::new(address_port)
+        .with_context(|| format!("invalid server address for ID={id}"))?
+        .timeout(opts.timeout);
+    let mut timeline = Timeline::default();
     barrier.wait().await;
+    let epoch = epoch.get();
 
     // Issue the "cold" request (also timing it with the global timer)
     let global_start = Instant::now();
-    let cold = bcmd.issue(address_port).await?;
-    let global = Instant::now() - global_start;
+    if opts.reuse_connection {
+        client.connect().await?;
+        timeline.record(Phase::Connect, epoch, global_start, Instant::now());
+    }
+    let start = Instant::now();
+    let cold = issue(bcmd, &client).await?;
+    let cold_end = Instant::now();
+    timeline.record(Phase::Cold, epoch, start, cold_end);
+    let global = cold_end - global_start;
     barrier.wait().await;
 
     // Asynchronously pre-warm in parallel, if necessary
-    if num_prewarm > 0 {
-        let mut rng: StdRng = SeedableRng::from_entropy();
-        for i in 0..num_prewarm {
-            let _ = bcmd
-                .issue(addr_port.clone())
-                .await
-                .with_context(|| format!("ID={id} failed during pre-warming (round: {i})"))?;
-            sleep(Duration::from_millis(rng.gen_range(20..120))).await;
-        }
+    for (start, end) in pre_warm(&client, bcmd.rpc_args(), opts.pre_warm)
+        .await
+        .with_context(|| format!("ID={id} failed during pre-warming"))?
+    {
+        timeline.record(Phase::PreWarm, epoch, start, end);
     }
     barrier.wait().await;
 
     // Issue the "warm" request
-    let warm = bcmd.issue(addr_port).await?;
+    let start = Instant::now();
+    let warm = issue(bcmd, &client).await?;
+    timeline.record(Phase::Warm, epoch, start, Instant::now());
     barrier.wait().await;
 
-    let m = (global, cold.into(), warm.into()).into();
-    Ok((id, m))
+    let m: Measurement = (global, cold.try_into()?, warm.try_into()?).into();
+    Ok(m.with_timeline(timeline))
 }
 
-/// A standalone worker task's routine in case the `restore` subcommand has been provided.
-async fn task_restore(
+/// The number of times [`task_load`] waits on the shared [`Barrier`].
+const LOAD_RENDEZVOUS: usize = 3;
+
+/// A standalone worker task's routine in case the `load` subcommand has been provided.
+async fn task_load(
     id: usize,
     address_port: String,
-    mut rcmd: RestoreCmd,
-    num_prewarm: usize,
-    barrier: Arc<Barrier>,
-) -> Result<(usize, Measurement)> {
-    // Validation, pre-processing and allocations (before the timer begins)
-    rcmd.validate(id)
-        .with_context(|| format!("failed to validate arguments for ID={id}"))?;
-    let addr_port = address_port.clone();
+    lcmd: &LoadCmd,
+    opts: WorkerOpts,
+    load: LoadOpts,
+    barrier: &mut Rendezvous,
+) -> Result<LoadReport> {
+    // Allocations & connection (all requests are multiplexed over it)
+    let mut client = BenchClient::new(address_port)
+        .with_context(|| format!("invalid server address for ID={id}"))?
+        .timeout(opts.timeout);
+    client.connect().await?;
+    let args = lcmd.bench.rpc_args();
     barrier.wait().await;
 
-    // Start the global timer and restore the uVM from the snapshot
-    let global_start = Instant::now();
-    let restore = rcmd.restore().await?;
+    // Issue the "cold" request, and then pre-warm, if necessary
+    client.bench(args).await?;
+    pre_warm(&client, args, opts.pre_warm)
+        .await
+        .with_context(|| format!("ID={id} failed during pre-warming"))?;
     barrier.wait().await;
 
-    // Resume the uVM restored from the snapshot
-    let resume = rcmd.resume().await?;
+    // Load all MicroVMs at the same time
+    let report = open_loop(&client, args, load).await;
     barrier.wait().await;
 
-    // Issue the "cold" request and stop the global timer
-    let cold = rcmd.bench.issue(address_port).await?;
-    let global = Instant::now() - global_start;
-    barrier.wait().await;
+    Ok(report)
+}
 
-    // Asynchronously pre-warm in parallel, if necessary
-    if num_prewarm > 0 {
-        let mut rng: StdRng = SeedableRng::from_entropy();
-        for i in 0..num_prewarm {
-            let _ = rcmd
-                .bench
-                .issue(addr_port.clone())
-                .await
-                .with_context(|| format!("ID={id} failed during pre-warming (round: {i})"))?;
-            sleep(Duration::from_millis(rng.gen_range(20..120))).await;
-        }
+/// A standalone worker task's routine in case the `restore` subcommand has been provided; the
+/// [`ResidencyReport`] of the MicroVM's files is only produced if `residency_report` is set.
+async fn task_restore(
+    id: usize,
+    address_port: String,
+    mut rcmd: RestoreCmd,
+    opts: WorkerOpts,
+    arrival: Arrival,
+    residency_report: bool,
+    barrier: &mut Rendezvous,
+) -> Result<(Measurement, Option<ResidencyReport>)> {
+    // Validation, pre-processing and allocations (before the timer begins)
+    rcmd.validate(id)
+        .with_context(|| format!("failed to validate arguments for ID={id}"))?;
+    let client = BenchClient::new(address_port)
+        .with_context(|| format!("invalid server address for ID={id}"))?
+        .timeout(opts.timeout);
+    let target = rcmd.target();
+    let files = rcmd.files();
+
+    let ctx = || {
+        format!(
+            "ID={id} failed to restore & benchmark the MicroVM behind '{}'",
+            rcmd.api_sock_path.display()
+        )
+    };
+    let args = rcmd.bench.rpc_args();
+    if residency_report {
+        restore_and_bench_probed(&target, client, args, opts, &arrival, &files, barrier)
+            .await
+            .map(|(m, residency)| (m, Some(residency)))
+            .with_context(ctx)
+    } else {
+        restore_and_bench(&target, client, args, opts, &arrival, barrier)
+            .await
+            .map(|m| (m, None))
+            .with_context(ctx)
     }
-    barrier.wait().await;
-
-    // Issue the "warm" request
-    let warm = rcmd.bench.issue(addr_port).await?;
-    barrier.wait().await;
-
-    let m = (global, restore, resume, cold.into(), warm.into()).into();
-    Ok((id, m))
 }
 
 #[tokio::main]
@@ -403,47 +569,272 @@ async fn main() -> Result<()> {
     // Prepend scheme to every `ADDR:PORT`, to be ready for use in a URL.
     addrs.iter_mut().for_each(|s| s.insert_str(0, "http://"));
 
-    // (Un)initialize a Vec to add the resulting Measurements as they are received from the workers
-    let mut measurements: Vec<MaybeUninit<Measurement>> = Vec::with_capacity(cli.num_uvms);
-    // SAFETY: This `Vec` will not be read before it gets filled up with the (properly initialized)
-    // `Measurement` structs that the worker tasks return upon their completion.
-    unsafe { measurements.set_len(cli.num_uvms) };
+    if cli.residency_report && !matches!(cli.top_cmd, TopSubcommand::Restore(_)) {
+        bail!("'--residency-report' only applies to the 'restore' subcommand");
+    }
+    if !matches!(
+        cli.top_cmd,
+        TopSubcommand::Issue(_) | TopSubcommand::Restore(_)
+    ) {
+        if cli.timeline.is_some() {
+            bail!("'--timeline' only applies to the 'issue' and 'restore' subcommands");
+        }
+        if cli.trace_out.is_some() {
+            bail!("'--trace-out' only applies to the 'issue' and 'restore' subcommands");
+        }
+    }
+    if cli.trace_memory_interval == Some(Duration::ZERO) {
+        bail!("'--trace-memory-interval' must be positive");
+    }
+    match &cli.top_cmd {
+        TopSubcommand::Load(lcmd) => return run_load(&cli, lcmd, addrs).await,
+        TopSubcommand::Replay(pcmd) => return run_replay(&cli, pcmd, addrs).await,
+        TopSubcommand::Issue(_) | TopSubcommand::Restore(_) => (),
+    }
 
     // Spawn the tasks that do the actual work (depending on the provided subcommand)
+    let opts = cli.worker_opts();
+    let residency_report = cli.residency_report;
+    let schedule = match &cli.top_cmd {
+        TopSubcommand::Restore(rcmd) => RestoreSchedule::new(rcmd.arrivals, cli.num_uvms),
+        _ => RestoreSchedule::new(RestoreArrivals::Simultaneous, cli.num_uvms),
+    };
+    let epoch = schedule.epoch().clone();
+    let sampler = cli
+        .trace_memory_interval
+        .map(|interval| MemorySampler::spawn(epoch.clone(), interval));
     let mut workers = Vec::with_capacity(cli.num_uvms);
     let barrier = Arc::new(Barrier::new(cli.num_uvms));
     for (id, addr) in addrs.into_iter().enumerate() {
-        let barrier = barrier.clone();
+        let mut barrier = Rendezvous::new(barrier.clone());
         match &cli.top_cmd {
-            &TopSubcommand::Issue(bcmd) => workers.push(tokio::spawn(async move {
-                task_issue(id, addr, &bcmd, cli.pre_warm, barrier).await
-            })),
+            TopSubcommand::Issue(bcmd) => {
+                let bcmd = bcmd.clone();
+                let epoch = epoch.clone();
+                workers.push(tokio::spawn(async move {
+                    let res = task_issue(id, addr, &bcmd, opts, epoch, &mut barrier)
+                        .await
+                        .map(|m| (m, None));
+                    barrier.finish(ISSUE_RENDEZVOUS).await;
+                    (id, res)
+                }))
+            }
             TopSubcommand::Restore(rcmd) => {
                 let rcmd = rcmd.clone();
+                let opts = WorkerOpts {
+                    sync_clock: rcmd.sync_clock,
+                    ..opts
+                };
+                let arrival = schedule.arrival(id);
                 workers.push(tokio::spawn(async move {
-                    task_restore(id, addr, rcmd, cli.pre_warm, barrier).await
+                    let res = task_restore(
+                        id,
+                        addr,
+                        rcmd,
+                        opts,
+                        arrival,
+                        residency_report,
+                        &mut barrier,
+                    )
+                    .await;
+                    barrier.finish(RESTORE_RENDEZVOUS).await;
+                    (id, res)
                 }))
             }
+            TopSubcommand::Load(_) | TopSubcommand::Replay(_) => unreachable!(),
         };
     }
 
-    // Join all tasks
-    for res in try_join_all(workers)
+    // Join all tasks, reporting any failed MicroVMs to stderr
+    let mut measurements: Vec<Option<(Measurement, Option<ResidencyReport>)>> =
+        (0..cli.num_uvms).map(|_| None).collect();
+    let mut failures = 0;
+    for (id, res) in try_join_all(workers)
         .await
         .with_context(|| "could not join worker tasks")?
     {
-        let (i, m) = res.with_context(|| "failed to retrieve workers' results after joining")?;
-        measurements[i].write(m);
+        match res {
+            Ok(m) => measurements[id] = Some(m),
+            Err(err) => {
+                failures += 1;
+                let kind = err.downcast_ref::<Error>().map_or("other", Error::kind);
+                eprintln!("ID={id} failed ({kind}): {err:#}");
+            }
+        }
+    }
+
+    let memory = match sampler {
+        Some(sampler) => sampler
+            .stop()
+            .await
+            .with_context(|| "failed to sample the host's memory usage")?,
+        None => Vec::new(),
+    };
+
+    // Print resulting Measurements to stdout; the arrivals are only reported if they were spread
+    // in time, so that the rows keep their usual shape otherwise
+    let sync_clock = matches!(&cli.top_cmd, TopSubcommand::Restore(rcmd) if rcmd.sync_clock);
+    let arrivals = matches!(
+        &cli.top_cmd,
+        TopSubcommand::Restore(rcmd) if rcmd.arrivals != RestoreArrivals::Simultaneous
+    );
+    let mut out = MeasurementWriter::new(io::stdout().lock(), cli.output_format, true)
+        .time_unit(cli.time_unit)
+        .arrivals(arrivals)
+        .residency(cli.residency_report)
+        .sync_clock(sync_clock);
+    for (id, measurement) in measurements.iter().enumerate() {
+        let res = match measurement {
+            Some((m, Some(residency))) => out.write_with_residency(id, m, residency),
+            Some((m, None)) => out.write(id, m),
+            None => continue,
+        };
+        res.with_context(|| "failed to write measurements to stdout")?;
+    }
+    out.finish()
+        .with_context(|| "failed to write measurements to stdout")?;
+
+    if let Some(path) = &cli.timeline {
+        write_timelines(&cli, path, &measurements)
+            .with_context(|| format!("failed to write the timelines to '{}'", path.display()))?;
+    }
+    if let Some(path) = &cli.trace_out {
+        write_trace(path, &measurements, &memory)
+            .with_context(|| format!("failed to write the trace to '{}'", path.display()))?;
     }
-    // SAFETY: This `Vec` was pre-allocated to be of the exact size (in # of `Measurement` structs)
-    // as the number of MicroVMs, which is also the number of worker tasks, and thus must have been
-    // fully initialized upon their completion; therefore, it is filled up with properly
-    // initialized `Measurement` structs by now.
-    let measurements = unsafe { std::mem::transmute::<_, Vec<Measurement>>(measurements) };
 
-    // Print resulting Measurements to stdout
+    if failures > 0 {
+        bail!("{failures} out of {} MicroVMs failed", cli.num_uvms);
+    }
+    Ok(())
+}
+
+/// Write the [`Timeline`] of each MicroVM that was measured to the file at `path`.
+fn write_timelines(
+    cli: &Cli,
+    path: &Path,
+    measurements: &[Option<(Measurement, Option<ResidencyReport>)>],
+) -> io::Result<()> {
+    let f = BufWriter::new(File::create(path)?);
+    let mut out = TimelineWriter::new(f, cli.output_format).time_unit(cli.time_unit);
+    for (id, measurement) in measurements.iter().enumerate() {
+        if let Some((m, _)) = measurement {
+            out.write(id, m.timeline())?;
+        }
+    }
+    out.finish()
+}
+
+/// Write the [`Timeline`] of each MicroVM that was measured, along with any samples of the host's
+/// `memory` usage, to the file at `path`, in the Chrome trace-event format.
+fn write_trace(
+    path: &Path,
+    measurements: &[Option<(Measurement, Option<ResidencyReport>)>],
+    memory: &[MemorySample],
+) -> io::Result<()> {
+    let mut out = TraceWriter::new(BufWriter::new(File::create(path)?));
     for (id, measurement) in measurements.iter().enumerate() {
-        println!("{},{}", id, measurement);
+        if let Some((m, _)) = measurement {
+            out.write(id, m.timeline())?;
+        }
+    }
+    out.write_memory(memory)?;
+    out.finish()
+}
+
+/// Load all MicroVMs at `addrs` in parallel, according to the `load` subcommand, and print the
+/// resulting summaries to stdout.
+async fn run_load(cli: &Cli, lcmd: &LoadCmd, addrs: Vec<String>) -> Result<()> {
+    let opts = cli.worker_opts();
+    let load = lcmd.opts()?;
+    let mut workers = Vec::with_capacity(cli.num_uvms);
+    let barrier = Arc::new(Barrier::new(cli.num_uvms));
+    for (id, addr) in addrs.into_iter().enumerate() {
+        let mut barrier = Rendezvous::new(barrier.clone());
+        let lcmd = lcmd.clone();
+        workers.push(tokio::spawn(async move {
+            let res = task_load(id, addr, &lcmd, opts, load, &mut barrier).await;
+            barrier.finish(LOAD_RENDEZVOUS).await;
+            (id, res)
+        }));
+    }
+
+    // Join all tasks, reporting any failed MicroVMs to stderr
+    let mut reports: Vec<Option<LoadReport>> = (0..cli.num_uvms).map(|_| None).collect();
+    let mut failures = 0;
+    for (id, res) in try_join_all(workers)
+        .await
+        .with_context(|| "could not join worker tasks")?
+    {
+        match res {
+            Ok(report) => reports[id] = Some(report),
+            Err(err) => {
+                failures += 1;
+                let kind = err.downcast_ref::<Error>().map_or("other", Error::kind);
+                eprintln!("ID={id} failed ({kind}): {err:#}");
+            }
+        }
+    }
+
+    // Print the summary of each MicroVM, followed by the aggregate one, to stdout
+    let mut out = LoadWriter::new(io::stdout().lock(), cli.output_format).time_unit(cli.time_unit);
+    let mut aggregate = LoadReport::default();
+    for (id, report) in reports.iter().enumerate() {
+        if let Some(report) = report {
+            out.write(id, &report.summary())
+                .with_context(|| "failed to write load summaries to stdout")?;
+            aggregate.merge(report);
+        }
+    }
+    out.write_aggregate(&aggregate.summary())
+        .and_then(|_| out.finish())
+        .with_context(|| "failed to write load summaries to stdout")?;
+
+    if failures > 0 {
+        bail!("{failures} out of {} MicroVMs failed", cli.num_uvms);
+    }
+    Ok(())
+}
+
+/// Replay the trace of the `replay` subcommand on a pool of MicroVMs at `addrs`, and print the
+/// records of all invocations to stdout.
+async fn run_replay(cli: &Cli, pcmd: &ReplayCmd, addrs: Vec<String>) -> Result<()> {
+    let trace = Trace::from_file(&pcmd.trace)?;
+    let uvms = pcmd
+        .uvms(&trace, addrs)
+        .with_context(|| "failed to validate the snapshots of the trace's functions")?;
+    let opts = ReplayOpts {
+        fc_bin: pcmd.fc_bin.clone(),
+        keep_alive: pcmd.keep_alive,
+    };
+    let records = replay(&trace, uvms, &opts)
+        .await
+        .with_context(|| "failed to replay the trace")?;
+
+    // Print all records to stdout, reporting any failed invocations to stderr
+    let mut out =
+        InvocationWriter::new(io::stdout().lock(), cli.output_format).time_unit(cli.time_unit);
+    let (mut cold, mut failures) = (0, 0);
+    for record in &records {
+        if record.start == Some(Start::Cold) {
+            cold += 1;
+        }
+        if let (Some(kind), Some(message)) = (record.error, &record.message) {
+            failures += 1;
+            eprintln!("Invocation #{} failed ({kind}): {message}", record.index);
+        }
+        out.write(record)
+            .with_context(|| "failed to write invocation records to stdout")?;
+    }
+    out.finish()
+        .with_context(|| "failed to write invocation records to stdout")?;
+    eprintln!(
+        "Replayed {} invocations ({cold} cold starts).",
+        records.len()
+    );
+
+    if failures > 0 {
+        bail!("{failures} out of {} invocations failed", records.len());
     }
     Ok(())
 }
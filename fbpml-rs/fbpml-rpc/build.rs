@@ -1,4 +1,5 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=../../proto/functionbench_pmem_local.proto");
     tonic_build::configure()
         .build_client(true)
         .build_server(false)
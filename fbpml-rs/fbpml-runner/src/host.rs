@@ -0,0 +1,154 @@
+//! Host-related helpers: CPU topology, TAP interfaces and ownership of the results.
+
+use std::{
+    env, fs, io, mem,
+    os::unix::fs as unix_fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+/// The prefix of the names of the TAP interfaces of the MicroVMs (see `scripts/host_net.sh`).
+const TAP_PREFIX: &str = "fcpmem01";
+
+/// Parse a list of CPUs in the format used by the kernel (e.g., `0-3,8,10-11`).
+fn parse_cpulist(list: &str) -> Result<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let (start, end): (usize, usize) = (
+            start
+                .parse()
+                .with_context(|| format!("invalid CPU list '{list}'"))?,
+            end.parse()
+                .with_context(|| format!("invalid CPU list '{list}'"))?,
+        );
+        cpus.extend(start..=end);
+    }
+    Ok(cpus)
+}
+
+/// The logical CPUs of the given NUMA node.
+pub fn node_cpus(node: usize) -> Result<Vec<usize>> {
+    let path = format!("/sys/devices/system/node/node{node}/cpulist");
+    let list = fs::read_to_string(&path)
+        .with_context(|| format!("failed to read the CPUs of NUMA node {node} from '{path}'"))?;
+    let cpus = parse_cpulist(&list)?;
+    if cpus.is_empty() {
+        bail!("NUMA node {node} has no CPUs");
+    }
+    Ok(cpus)
+}
+
+/// The physical cores of the given NUMA node; i.e., only the first hardware thread of each core.
+pub fn node_physical_cores(node: usize) -> Result<Vec<usize>> {
+    let mut cores = Vec::new();
+    for cpu in node_cpus(node)? {
+        let path = format!("/sys/devices/system/cpu/cpu{cpu}/topology/thread_siblings_list");
+        let siblings = parse_cpulist(
+            &fs::read_to_string(&path)
+                .with_context(|| format!("failed to read the siblings of CPU {cpu}"))?,
+        )?;
+        if siblings.first().is_none_or(|&first| first == cpu) {
+            cores.push(cpu);
+        }
+    }
+    Ok(cores)
+}
+
+/// Make sure that (at least) `expected` TAP interfaces for the MicroVMs are currently present.
+pub fn check_taps(expected: usize) -> Result<()> {
+    let found = fs::read_dir("/sys/class/net")
+        .with_context(|| "failed to list the network interfaces")?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(TAP_PREFIX))
+        .count();
+    if found < expected {
+        bail!("expected {expected} '{TAP_PREFIX}.*' tap interfaces; found {found}");
+    }
+    Ok(())
+}
+
+/// Whether the runner is running as root.
+pub fn is_root() -> bool {
+    // SAFETY: geteuid(2) is always successful.
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// The paths of all entries of `dir` that have the given extension.
+pub fn files_with_extension(dir: &Path, extension: &str) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("failed to list '{}'", dir.display()))? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == extension) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Hand `path` (recursively) over to the user (and group) that invoked the runner through
+/// `sudo(8)`, if any, so that they can manage the results without root privileges; symbolic links
+/// are not followed.
+pub fn chown_to_invoking_user(path: &Path) -> Result<()> {
+    if !is_root() {
+        return Ok(());
+    }
+    let id = |var| env::var(var).ok().and_then(|id| id.parse().ok());
+    let uid = match id("SUDO_UID") {
+        Some(uid) => uid,
+        None => return Ok(()),
+    };
+    let gid = id("SUDO_GID");
+
+    fn chown_all(path: &Path, uid: u32, gid: Option<u32>) -> io::Result<()> {
+        unix_fs::lchown(path, Some(uid), gid)?;
+        if fs::symlink_metadata(path)?.is_dir() {
+            for entry in fs::read_dir(path)? {
+                chown_all(&entry?.path(), uid, gid)?;
+            }
+        }
+        Ok(())
+    }
+    chown_all(path, uid, gid).with_context(|| format!("failed to chown '{}'", path.display()))
+}
+
+/// The current local time, in the `%Y%m%d%H%M%S` format.
+pub fn timestamp() -> Result<String> {
+    // SAFETY: `tm` is only read after `localtime_r(3)` has successfully initialized it.
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = mem::zeroed();
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            return Err(io::Error::last_os_error()).with_context(|| "localtime_r(3) failed");
+        }
+        tm
+    };
+    Ok(format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cpulists() {
+        assert_eq!(
+            parse_cpulist("0-3,8,10-11\n").unwrap(),
+            [0, 1, 2, 3, 8, 10, 11]
+        );
+        assert_eq!(parse_cpulist("5").unwrap(), [5]);
+        assert!(parse_cpulist("\n").unwrap().is_empty());
+        for list in ["0-", "a", "1-b", "0,,-2"] {
+            assert!(parse_cpulist(list).is_err(), "{list}");
+        }
+    }
+}
@@ -0,0 +1,812 @@
+mod host;
+mod snapcopy;
+mod spec;
+mod stage;
+
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use futures::future::{join_all, try_join_all};
+use tokio::{sync::Barrier, task};
+
+use fbpml::{
+    bench::Benchmark,
+    cache::{self, ResidencyReport, SnapshotFiles},
+    diff::{self, FileSize},
+    fcmetrics::{FcMetrics, RestoreLatencies},
+    firecracker::{Metrics, SnapshotLoadParams},
+    manifest::{Manifest, MANIFEST},
+    multi::{
+        restore_and_bench, restore_and_bench_probed, Rendezvous, RestoreArrivals, RestoreSchedule,
+        RestoreTarget, WorkerOpts, RESTORE_RENDEZVOUS,
+    },
+    output::{MeasurementWriter, OutputFormat},
+    vmm::{self, Vmm},
+    BenchClient, Error, FirecrackerApi, Measurement,
+};
+
+use crate::{
+    snapcopy::SnapcopyCmd,
+    spec::{Cell, Experiment, Progress, SnapshotKind},
+    stage::CleanCmd,
+};
+
+/// Run a benchmark on multiple MicroVMs, restored from snapshots that are staged on each one of the
+/// given devices, storing the results under `$OUTDIR/$BENCH/$DEVICE/runNN.csv`.
+///
+/// Alternatively, run a whole sweep described by an experiment specification (`--spec`), storing
+/// the results of each cell under `$OUTDIR/$BENCH/$DEVICE/$PARAMS/runNN.csv`; such an experiment
+/// can be resumed by running it again with the same output directory.
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+#[clap(propagate_version = true)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[clap(subcommand)]
+    cmd: Option<Cmd>,
+
+    /// Path to a TOML experiment specification to run, instead of a single benchmark.
+    #[clap(
+        long = "spec",
+        conflicts_with_all = &[
+            "pmem-path",
+            "nvme-path",
+            "ssd-path",
+            "pre-warm",
+            "reuse-connection",
+            "sync-clock",
+            "timeout",
+            "snapshots",
+        ]
+    )]
+    spec: Option<PathBuf>,
+
+    /// Resume the experiment in `--outdir` even if its specification differs from the one given
+    /// (e.g., because it was edited in the meantime), replacing the copy kept along with the
+    /// results.
+    #[clap(long = "overwrite-spec", requires = "spec")]
+    overwrite_spec: bool,
+
+    /// The name of the benchmark to run.
+    #[clap(
+        short = 'b',
+        long = "bench",
+        parse(try_from_str = Benchmark::lookup),
+        required_unless_present = "spec"
+    )]
+    bench: Option<&'static Benchmark>,
+
+    /// Number of MicroVMs to run in parallel.
+    #[clap(long = "num-uvms", env = "DEFAULT_MANY")]
+    num_uvms: Option<usize>,
+
+    /// Snapshots' directory on mounted PMEM device.
+    #[clap(short = 'p', long = "pmem-path")]
+    pmem_path: Option<PathBuf>,
+
+    /// Snapshots' directory on mounted NVMe device.
+    #[clap(short = 'n', long = "nvme-path")]
+    nvme_path: Option<PathBuf>,
+
+    /// Snapshots' directory on mounted Flash SSD device.
+    #[clap(short = 's', long = "ssd-path")]
+    ssd_path: Option<PathBuf>,
+
+    /// Number of runs on each device.
+    #[clap(short = 'r', long = "runs", env = "DEFAULT_RUNS")]
+    runs: Option<usize>,
+
+    /// Number of warm invocations to issue (after the cold one) before the warm invocation that is
+    /// actually going to be reported.
+    #[clap(short = 'w', long = "pre-warm", default_value = "0")]
+    pre_warm: usize,
+
+    /// Reuse the connection established for the "cold" request for all subsequent requests.
+    #[clap(long = "reuse-connection")]
+    reuse_connection: bool,
+
+    /// The snapshot to restore the MicroVMs from on each device; either 'full' or 'diffK' (i.e.,
+    /// the K-th diff snapshot created by `build-snapshots --diff-snapshots`, under
+    /// `$SNAPSHOT_DIR/$BENCH/diffK`). It may be repeated to compare them, in which case the results
+    /// of each diff snapshot are stored under `$OUTDIR/$BENCH/${DEVICE}_diffK`, and the sizes of
+    /// all snapshots in `$OUTDIR/$BENCH/snapshot-sizes.csv`.
+    #[clap(long = "snapshot", default_value = "full", multiple_occurrences = true)]
+    snapshots: Vec<SnapshotKind>,
+
+    /// Directory to store the resulting CSVs in; defaults to the experiment's `outdir`, if any, or
+    /// to `$DEFAULT_OUTDIR_<timestamp>`.
+    #[clap(short = 'o', long = "outdir")]
+    outdir: Option<PathBuf>,
+
+    /// Directory where the snapshots of all benchmarks initially live, as created by
+    /// `build-snapshots` (i.e., `$SNAPSHOT_DIR/$BENCH/{snapshot,memory}-$IDh.file`); the
+    /// experiment's `snapshot_dir` takes precedence, if any.
+    #[clap(long = "snapshot-dir", default_value = "snapshot")]
+    snapshot_dir: PathBuf,
+
+    /// Directory where the rootfs images of the uVMs are stored.
+    #[clap(long = "rootfs-dir", env = "ROOTFS_DIR", required = true)]
+    rootfs_dir: Option<PathBuf>,
+
+    /// Do not evict the rootfs images from the page cache before each run (e.g., because they live
+    /// on tmpfs, where they cannot be evicted).
+    #[clap(long = "keep-rootfs-cached")]
+    keep_rootfs_cached: bool,
+
+    /// Remove each benchmark's snapshots from the devices once all of its cells have been run, to
+    /// reclaim their space (see also the `clean` subcommand).
+    #[clap(long = "clean-staged")]
+    clean_staged: bool,
+
+    /// Report how many pages of each MicroVM's snapshot and rootfs files resided in the page cache
+    /// right before it was restored and after the "warm" request had been served, as additional
+    /// columns of the resulting CSVs.
+    #[clap(long = "residency-report")]
+    residency_report: bool,
+
+    /// Have each Firecracker process write its metrics next to the resulting CSV of each run (as
+    /// `runNN-fc-$IDh.metrics`), flush them once the MicroVMs have been benchmarked, and append
+    /// Firecracker's own `load_snapshot` and `resume_vm` latencies to the resulting CSVs.
+    #[clap(long = "fc-metrics")]
+    fc_metrics: bool,
+
+    /// Refuse to run benchmarks that fetch their input from MinIO from snapshots that are older
+    /// than the clock skew that MinIO tolerates (15min), as per their manifest; such stale
+    /// snapshots are only warned about otherwise.
+    #[clap(long = "refuse-stale")]
+    refuse_stale: bool,
+
+    /// Set each guest's clock to the host's right after resuming it (over the connection of the
+    /// "cold" request), so that snapshots of any age can be restored; it is excluded from the
+    /// global duration, and reported in the additional `sync_clock` and `clock_skew` columns of
+    /// the resulting CSVs.
+    #[clap(long = "sync-clock")]
+    sync_clock: bool,
+
+    /// Abandon any request to a MicroVM (or connection attempt) that takes longer than the given
+    /// (fractional) number of seconds, reporting the MicroVM as failed with a 'timeout'
+    /// error.
+    #[clap(long = "timeout", parse(try_from_str = parse_secs))]
+    timeout: Option<Duration>,
+
+    /// Path to the firecracker binary.
+    #[clap(long = "fc-bin", env = "FC_BIN", required = true)]
+    fc_bin: Option<PathBuf>,
+
+    /// The NUMA node on whose physical cores the Firecracker processes are pinned (which matters
+    /// for the NVDIMM bus).
+    #[clap(long = "fc-node", default_value = "0")]
+    fc_node: usize,
+
+    /// The NUMA node on which the runner itself (and therefore all gRPC clients) is pinned, so as
+    /// not to interfere with Firecracker.
+    #[clap(long = "client-node", default_value = "1")]
+    client_node: usize,
+
+    /// IP Address and TCP port format of the gRPC servers, where the `ID` substring is replaced by
+    /// each MicroVM's ID.
+    #[clap(long = "server-addr-fmt", default_value = "10.0.ID.2:50051")]
+    address_port_fmt: String,
+
+    /// Do not log progress to stderr.
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+}
+
+fn parse_secs(s: &str) -> Result<Duration> {
+    let secs: f64 = s
+        .parse()
+        .with_context(|| format!("invalid duration '{s}'"))?;
+    Duration::try_from_secs_f64(secs).with_context(|| format!("invalid duration '{s}'"))
+}
+
+/// Utilities that are also available as standalone subcommands.
+#[derive(Subcommand)]
+enum Cmd {
+    /// Copy a (snapshot) file with any of the strategies used for staging snapshots on the devices.
+    Snapcopy(SnapcopyCmd),
+    /// Remove the snapshots staged on the given devices, to reclaim their space.
+    Clean(CleanCmd),
+}
+
+impl Cli {
+    fn fc_bin(&self) -> Result<&Path> {
+        self.fc_bin
+            .as_deref()
+            .with_context(|| "no Firecracker binary was provided")
+    }
+
+    /// The directory of the rootfs images of the MicroVMs of `bench`.
+    fn rootfs_dir(&self, bench: &Benchmark) -> Result<PathBuf> {
+        self.rootfs_dir
+            .as_deref()
+            .map(|dir| dir.join(bench.name))
+            .with_context(|| "no rootfs directory was provided")
+    }
+
+    /// The cells to run when a single benchmark is specified through the command line flags: one
+    /// per device, storing its results under `$OUTDIR/$BENCH/$DEVICE`.
+    fn cells(&self, bench: &'static Benchmark, outdir: &Path) -> Result<Vec<Cell>> {
+        let num_uvms = self
+            .num_uvms
+            .with_context(|| "the number of MicroVMs was not provided")?;
+        let runs = self
+            .runs
+            .with_context(|| "the number of runs was not provided")?;
+        let cells: Vec<_> = [
+            (Device::Dcpm, &self.pmem_path),
+            (Device::Nvme, &self.nvme_path),
+            (Device::Ssd, &self.ssd_path),
+        ]
+        .into_iter()
+        .filter_map(|(device, path)| match path {
+            Some(path) => Some((device, path)),
+            None => {
+                eprintln!(
+                    "WARNING: Skipping runs on {}; no such path was provided.",
+                    device.name()
+                );
+                None
+            }
+        })
+        .flat_map(|(device, path)| {
+            self.snapshots.iter().map(move |&snapshot| {
+                let results = match snapshot {
+                    SnapshotKind::Full => device.name().to_string(),
+                    diff => format!("{}_{diff}", device.name()),
+                };
+                Cell {
+                    bench,
+                    rpc_args: bench.default_rpc_args(),
+                    device,
+                    device_path: path.clone(),
+                    num_uvms,
+                    mem_mib: None,
+                    vcpus: None,
+                    snapshot,
+                    opts: WorkerOpts {
+                        pre_warm: self.pre_warm,
+                        reuse_connection: self.reuse_connection,
+                        sync_clock: self.sync_clock,
+                        timeout: self.timeout,
+                    },
+                    runs,
+                    results: outdir.join(bench.name).join(results),
+                }
+            })
+        })
+        .collect();
+        if cells.is_empty() {
+            bail!("no device path was provided");
+        }
+        Ok(cells)
+    }
+
+    /// The output directory, if one was explicitly specified, or `$DEFAULT_OUTDIR_<timestamp>`.
+    fn outdir(&self, specified: Option<&Path>) -> Result<PathBuf> {
+        match self.outdir.as_deref().or(specified) {
+            Some(outdir) => Ok(outdir.to_path_buf()),
+            None => Ok(PathBuf::from(format!(
+                "{}_{}",
+                env::var("DEFAULT_OUTDIR").with_context(|| "no output directory was provided")?,
+                host::timestamp()?
+            ))),
+        }
+    }
+
+    fn log_progress(&self, cell: &Cell, run: usize) {
+        if !self.quiet {
+            eprintln!(
+                "[{}] Bench: {:>16}     Device: {:>6}     Snapshot: {:>6}     uVMs: {:>3}   :  run {run:2}",
+                host::timestamp().unwrap_or_default(),
+                cell.bench.name,
+                cell.device.name(),
+                cell.snapshot.to_string(),
+                cell.num_uvms,
+            );
+        }
+    }
+}
+
+/// The (classes of) devices that snapshots may be staged on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    /// Intel Optane DC Persistent Memory.
+    Dcpm,
+    /// NVMe SSD.
+    Nvme,
+    /// SATA Flash SSD.
+    Ssd,
+}
+
+impl Device {
+    /// The name of the device, as used in the results directory tree.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Dcpm => "dcpm",
+            Self::Nvme => "nvme",
+            Self::Ssd => "ssd",
+        }
+    }
+}
+
+/// Spawn the Firecracker process of MicroVM `id` of `bench`, pinned on `core`, and prepare
+/// everything needed to restore it from its snapshot in `dir` and to talk to it (along with the
+/// files backing it, if they are to be probed for the `--residency-report`); if `metrics` is
+/// given, Firecracker is configured to write its metrics there.
+async fn prepare(
+    cli: &Cli,
+    bench: &Benchmark,
+    id: usize,
+    core: usize,
+    dir: &Path,
+    metrics: Option<PathBuf>,
+) -> Result<(Vmm, RestoreTarget, BenchClient, Option<SnapshotFiles>)> {
+    let idh = format!("{id:02X}");
+    let sock = PathBuf::from(format!("/tmp/firecracker-{}-{idh}.socket", bench.name));
+    let mut vmm = Vmm::spawn(
+        cli.fc_bin()?,
+        &format!("{}-{idh}", bench.cli_name()),
+        sock,
+        Some(core),
+    )
+    .await?;
+    let api = vmm.wait_api().await?;
+    if let Some(metrics_path) = metrics {
+        // Firecracker does not create the metrics file itself
+        File::create(&metrics_path)
+            .with_context(|| format!("failed to create '{}'", metrics_path.display()))?;
+        api.put_metrics(&Metrics { metrics_path })
+            .await
+            .with_context(|| "failed to configure Firecracker's metrics")?;
+    }
+
+    let addr = format!(
+        "http://{}",
+        cli.address_port_fmt.replace("ID", &id.to_string())
+    );
+    let client = BenchClient::new(addr.clone())
+        .with_context(|| format!("invalid server address '{addr}'"))?;
+
+    let target = RestoreTarget {
+        api,
+        snapshot: SnapshotLoadParams {
+            snapshot_path: dir.join(format!("snapshot-{idh}.file")),
+            mem_file_path: dir.join(format!("memory-{idh}.file")),
+            enable_diff_snapshots: false,
+            resume_vm: false,
+        },
+    };
+    let files = if cli.residency_report {
+        Some(SnapshotFiles {
+            state: target.snapshot.snapshot_path.clone(),
+            memory: target.snapshot.mem_file_path.clone(),
+            rootfs: Some(
+                cli.rootfs_dir(bench)?
+                    .join(format!("{}-{idh}.ext4", bench.name)),
+            ),
+        })
+    } else {
+        None
+    };
+    Ok((vmm, target, client, files))
+}
+
+/// The path of the metrics file of MicroVM `id` for the run whose results are stored in `outfile`.
+fn metrics_path(outfile: &Path, id: usize) -> PathBuf {
+    let run = outfile.file_stem().unwrap_or_default().to_string_lossy();
+    outfile.with_file_name(format!("{run}-fc-{id:02X}.metrics"))
+}
+
+/// Flush the metrics of the Firecracker process behind `api` to `path`, and pick Firecracker's own
+/// latencies for restoring and resuming the MicroVM out of them.
+async fn fc_latencies(api: &FirecrackerApi, path: &Path) -> Result<RestoreLatencies> {
+    api.flush_metrics()
+        .await
+        .with_context(|| "failed to flush Firecracker's metrics")?;
+    let metrics = task::block_in_place(|| FcMetrics::from_file(path))?;
+    RestoreLatencies::from_metrics(&metrics)
+        .with_context(|| format!("no snapshot load is accounted for in '{}'", path.display()))
+}
+
+/// Restore, resume and benchmark all MicroVMs of `cell` once, from the snapshots in `dir`, writing
+/// the resulting measurements in `outfile`; returns the number of MicroVMs that failed.
+async fn run_once(
+    cli: &Cli,
+    cell: &Cell,
+    dir: &Path,
+    fc_cores: &[usize],
+    outfile: &Path,
+) -> Result<usize> {
+    let n = cell.num_uvms;
+
+    // Spawn all Firecracker instances and wait for their API sockets
+    let metrics = |id| cli.fc_metrics.then(|| metrics_path(outfile, id));
+    let prepared = join_all((0..n).map(|id| {
+        let core = fc_cores[id % fc_cores.len()];
+        prepare(cli, cell.bench, id, core, dir, metrics(id))
+    }))
+    .await;
+
+    // Spawn the tasks that restore & benchmark them in lockstep; MicroVMs that could not be set
+    // up still participate in all rendezvous points, so as not to block the rest.
+    let opts = cell.opts;
+    let args = &cell.rpc_args;
+    let barrier = Arc::new(Barrier::new(n));
+    let schedule = RestoreSchedule::new(RestoreArrivals::Simultaneous, n);
+    let mut vmms = Vec::with_capacity(n);
+    let mut apis = Vec::with_capacity(n);
+    let workers = prepared
+        .into_iter()
+        .enumerate()
+        .map(|(id, prep)| {
+            let mut barrier = Rendezvous::new(barrier.clone());
+            let arrival = schedule.arrival(id);
+            let args = args.clone();
+            let prep = prep.map(|(vmm, target, client, files)| {
+                vmms.push(vmm);
+                apis.push((id, target.api.clone()));
+                (target, client.timeout(opts.timeout), files)
+            });
+            tokio::spawn(async move {
+                let res = match prep {
+                    Ok((target, client, Some(files))) => restore_and_bench_probed(
+                        &target,
+                        client,
+                        &args,
+                        opts,
+                        &arrival,
+                        &files,
+                        &mut barrier,
+                    )
+                    .await
+                    .map(|(m, residency)| (m, Some(residency)))
+                    .map_err(anyhow::Error::from),
+                    Ok((target, client, None)) => {
+                        restore_and_bench(&target, client, &args, opts, &arrival, &mut barrier)
+                            .await
+                            .map(|m| (m, None))
+                            .map_err(anyhow::Error::from)
+                    }
+                    Err(err) => Err(err.context("failed to set up the Firecracker process")),
+                };
+                barrier.finish(RESTORE_RENDEZVOUS).await;
+                (id, res)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // Join all tasks, reporting any failed MicroVMs to stderr
+    let mut measurements: Vec<Option<(Measurement, Option<ResidencyReport>)>> =
+        (0..n).map(|_| None).collect();
+    let mut failures = 0;
+    for (id, res) in try_join_all(workers)
+        .await
+        .with_context(|| "could not join worker tasks")?
+    {
+        match res {
+            Ok(m) => measurements[id] = Some(m),
+            Err(err) => {
+                failures += 1;
+                let kind = err.downcast_ref::<Error>().map_or("other", Error::kind);
+                eprintln!("ID={id} failed ({kind}): {err:#}");
+            }
+        }
+    }
+
+    // Collect Firecracker's own latencies for all MicroVMs that were measured
+    let mut latencies: Vec<Option<RestoreLatencies>> = vec![None; n];
+    if cli.fc_metrics {
+        for (id, api) in apis.iter().filter(|(id, _)| measurements[*id].is_some()) {
+            match fc_latencies(api, &metrics_path(outfile, *id)).await {
+                Ok(l) => latencies[*id] = Some(l),
+                Err(err) => eprintln!("ID={id}: no Firecracker latencies: {err:#}"),
+            }
+        }
+    }
+
+    // Terminate all MicroVMs, so that all API sockets and tap interfaces are released for the
+    // next run
+    join_all(vmms.into_iter().map(Vmm::terminate))
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| "failed to terminate all Firecracker processes")?;
+
+    // Store the resulting Measurements
+    let ctx = || format!("failed to write measurements to '{}'", outfile.display());
+    let mut out = MeasurementWriter::new(
+        BufWriter::new(File::create(outfile).with_context(ctx)?),
+        OutputFormat::Csv,
+        true,
+    )
+    .residency(cli.residency_report)
+    .fc_latencies(cli.fc_metrics)
+    .sync_clock(cell.opts.sync_clock);
+    for (id, measurement) in measurements.iter().enumerate() {
+        if let Some((m, residency)) = measurement {
+            out.write_with_reports(id, m, residency.as_ref(), latencies[id].as_ref())
+                .with_context(ctx)?;
+        }
+    }
+    out.finish().with_context(ctx)?;
+
+    Ok(failures)
+}
+
+/// Make sure that the snapshots of `cell` in `src` were built for its benchmark and MicroVM size,
+/// as per their manifest, which is returned (if there is one).
+fn check_manifest(cell: &Cell, src: &Path) -> Result<Option<Manifest>> {
+    let manifest = match Manifest::load(src)? {
+        Some(manifest) => manifest,
+        None => return Ok(None),
+    };
+    let path = src.join(MANIFEST);
+    for id in 0..cell.num_uvms {
+        manifest.check(
+            &path,
+            cell.bench,
+            &src.join(format!("snapshot-{id:02X}.file")),
+            &src.join(format!("memory-{id:02X}.file")),
+        )?;
+    }
+    if cell
+        .mem_mib
+        .is_some_and(|mem_mib| mem_mib != manifest.mem_size_mib)
+        || cell.vcpus.is_some_and(|vcpus| vcpus != manifest.vcpu_count)
+    {
+        bail!(
+            "'{}' describes snapshots of {}MiB-{}vcpu MicroVMs",
+            path.display(),
+            manifest.mem_size_mib,
+            manifest.vcpu_count
+        );
+    }
+    Ok(Some(manifest))
+}
+
+/// Warn about (or, with `--refuse-stale`, fail on) any snapshots of `cell` in `src` that have become
+/// too old for its benchmark to reach MinIO, as per their `manifest`, unless the guests' clocks are
+/// going to be set right after resuming them.
+fn check_fresh(cli: &Cli, cell: &Cell, src: &Path, manifest: &Manifest) -> Result<()> {
+    if cell.opts.sync_clock {
+        return Ok(());
+    }
+    for entry in manifest.snapshots.iter().filter(|e| e.id < cell.num_uvms) {
+        if let Err(err) = entry.check_fresh(cell.bench, &src.join(&entry.state.name)) {
+            if cli.refuse_stale {
+                return Err(err.into());
+            }
+            eprintln!("WARNING: {err}");
+        }
+    }
+    Ok(())
+}
+
+/// The name of the file (in each benchmark's results directory, next to those of its devices) that
+/// records the sizes of the snapshot files its MicroVMs are restored from, when diff snapshots are
+/// compared with full ones.
+const SNAPSHOT_SIZES: &str = "snapshot-sizes.csv";
+
+/// Record the sizes of the snapshot files of the MicroVMs of all `cells` of `bench`, as stored under
+/// `snapshot_dir` by `build-snapshots`, in `$OUTDIR/$BENCH/snapshot-sizes.csv`; the memory file of a
+/// diff snapshot is reported as written by Firecracker, i.e., before being layered on top of its
+/// base.
+fn write_snapshot_sizes(
+    bench: &Benchmark,
+    cells: &[Cell],
+    snapshot_dir: &Path,
+    outdir: &Path,
+) -> Result<()> {
+    // Each snapshot is shared by the cells on all devices, and by those of any number of MicroVMs
+    let mut snapshots = BTreeMap::new();
+    for cell in cells.iter().filter(|cell| cell.bench == bench) {
+        let (_, num_uvms) = snapshots.entry(cell.snapshot_subdir()).or_insert((cell, 0));
+        *num_uvms = cell.num_uvms.max(*num_uvms);
+    }
+
+    let path = outdir.join(bench.name).join(SNAPSHOT_SIZES);
+    let ctx = || format!("failed to write '{}'", path.display());
+    fs::create_dir_all(outdir.join(bench.name)).with_context(ctx)?;
+    let mut out = BufWriter::new(File::create(&path).with_context(ctx)?);
+    writeln!(
+        out,
+        "mem_mib,vcpus,snapshot,id,state,memory,memory_allocated"
+    )
+    .with_context(ctx)?;
+    for (subdir, (cell, num_uvms)) in snapshots {
+        let src = snapshot_dir.join(subdir);
+        for id in 0..num_uvms {
+            let state = FileSize::of(&src.join(format!("snapshot-{id:02X}.file")))?;
+            let memory = match cell.snapshot {
+                SnapshotKind::Full => FileSize::of(&src.join(format!("memory-{id:02X}.file")))?,
+                SnapshotKind::Diff(_) => FileSize::of(&src.join(diff::layer_name(id)))?,
+            };
+            writeln!(
+                out,
+                "{},{},{},{id},{},{},{}",
+                cell.mem_mib.map(|n| n.to_string()).unwrap_or_default(),
+                cell.vcpus.map(|n| n.to_string()).unwrap_or_default(),
+                cell.snapshot,
+                state.len,
+                memory.len,
+                memory.allocated
+            )
+            .with_context(ctx)?;
+        }
+    }
+    out.flush().with_context(ctx)
+}
+
+/// Stage the snapshots of `cell` on its device and run it for all its runs, skipping those that
+/// `progress` (if any) records as completed; returns the number of MicroVMs that failed across all
+/// runs.
+///
+/// Runs in which any MicroVM failed are not recorded as completed, so that they are repeated when
+/// the experiment is resumed.
+async fn run_cell(
+    cli: &Cli,
+    cell: &Cell,
+    snapshot_dir: &Path,
+    fc_cores: &[usize],
+    mut progress: Option<&mut Progress>,
+) -> Result<usize> {
+    let pending: Vec<_> = (1..=cell.runs)
+        .map(|run| (run, cell.results.join(format!("run{run:02}.csv"))))
+        .filter(|(_, outfile)| progress.as_ref().is_none_or(|p| !p.is_done(outfile)))
+        .collect();
+    if pending.is_empty() {
+        return Ok(0);
+    }
+    fs::create_dir_all(&cell.results)
+        .with_context(|| format!("failed to create '{}'", cell.results.display()))?;
+
+    // Stage benchmark's snapshot files in the given path, unless they are already there
+    let src = snapshot_dir.join(cell.snapshot_subdir());
+    let dir = cell.device_path.join(cell.snapshot_subdir());
+    let manifest = check_manifest(cell, &src)?;
+    task::block_in_place(|| stage::stage_snapshots(cell.device, &src, &dir, cell.num_uvms))?;
+
+    // Begin the runs for this cell
+    let mut failures = 0;
+    for (run, outfile) in pending {
+        // Remove snapshot and rootfs files from the page cache
+        let mut files = host::files_with_extension(&dir, "file")?;
+        if !cli.keep_rootfs_cached {
+            files.extend(host::files_with_extension(
+                &cli.rootfs_dir(cell.bench)?,
+                "ext4",
+            )?);
+        }
+        task::block_in_place(|| cache::evict_all(&files))
+            .with_context(|| "failed to guarantee a cold restore")?;
+
+        if let Some(manifest) = &manifest {
+            check_fresh(cli, cell, &src, manifest)?;
+        }
+        cli.log_progress(cell, run);
+        let failed = run_once(cli, cell, &dir, fc_cores, &outfile)
+            .await
+            .with_context(|| format!("run {run} on {} failed", cell.device.name()))?;
+        if failed == 0 {
+            if let Some(progress) = progress.as_deref_mut() {
+                progress.mark_done(&outfile)?;
+            }
+        }
+        failures += failed;
+    }
+    Ok(failures)
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let experiment = cli.spec.as_deref().map(Experiment::from_file).transpose()?;
+
+    // Expand the experiment (or the command line flags) into the cells to run
+    let (outdir, cells, snapshot_dir, mut progress) = match (&experiment, cli.bench) {
+        (Some(experiment), _) => {
+            let outdir = cli.outdir(experiment.outdir.as_deref())?;
+            let cells = experiment.cells(&outdir)?;
+            fs::create_dir_all(&outdir)
+                .with_context(|| format!("failed to create '{}'", outdir.display()))?;
+            experiment.save(&outdir, cli.overwrite_spec)?;
+            let progress = Progress::open(&outdir)?;
+            if !cli.quiet {
+                eprintln!(
+                    "Running {} cells; results are stored under '{}' (pass `--outdir` to resume).",
+                    cells.len(),
+                    outdir.display()
+                );
+            }
+            let snapshot_dir = experiment
+                .snapshot_dir
+                .as_ref()
+                .unwrap_or(&cli.snapshot_dir);
+            (outdir, cells, snapshot_dir.clone(), Some(progress))
+        }
+        (None, Some(bench)) => {
+            let outdir = cli.outdir(None)?;
+            let cells = cli.cells(bench, &outdir)?;
+            (outdir, cells, cli.snapshot_dir.clone(), None)
+        }
+        (None, None) => bail!("neither a benchmark nor an experiment was provided"),
+    };
+
+    // Make sure all TAP interfaces are there
+    host::check_taps(cells.iter().map(|cell| cell.num_uvms).max().unwrap_or(0))?;
+    let fc_cores = host::node_physical_cores(cli.fc_node)?;
+
+    let mut failures = 0;
+    for (i, cell) in cells.iter().enumerate() {
+        // Cells are grouped by benchmark, so this is the first one of its benchmark if the previous
+        // one is of another
+        let first = i == 0 || cells[i - 1].bench != cell.bench;
+        let compares_diffs = || {
+            cells
+                .iter()
+                .any(|other| other.bench == cell.bench && other.snapshot.is_diff())
+        };
+        if first && compares_diffs() {
+            write_snapshot_sizes(cell.bench, &cells, &snapshot_dir, &outdir)?;
+        }
+        failures += run_cell(&cli, cell, &snapshot_dir, &fc_cores, progress.as_mut()).await?;
+
+        // Cells are grouped by benchmark, so this was the last one of its benchmark if the next
+        // one is of another
+        let last = cells.get(i + 1).is_none_or(|next| next.bench != cell.bench);
+        if cli.clean_staged && last {
+            let mut dirs: Vec<_> = cells
+                .iter()
+                .filter(|other| other.bench == cell.bench)
+                .map(|other| other.device_path.join(cell.bench.name))
+                .collect();
+            dirs.sort();
+            dirs.dedup();
+            for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+                let freed = task::block_in_place(|| stage::clean(dir))?;
+                if !cli.quiet {
+                    eprintln!(
+                        "Freed {:.1} MiB from '{}'",
+                        freed as f64 / (1 << 20) as f64,
+                        dir.display()
+                    );
+                }
+            }
+        }
+    }
+
+    host::chown_to_invoking_user(&outdir)?;
+    if failures > 0 {
+        bail!("{failures} MicroVM run(s) failed in total");
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    // The file is optional: clap reports the variables that are actually missing
+    dotenv::from_filename("config").ok();
+    let cli = Cli::parse();
+    match &cli.cmd {
+        Some(Cmd::Snapcopy(cmd)) => return cmd.run(),
+        Some(Cmd::Clean(cmd)) => return cmd.run(),
+        None => (),
+    }
+
+    // Pin the runner before the runtime spawns any threads, so that they all inherit its affinity
+    vmm::set_affinity(&host::node_cpus(cli.client_node)?)
+        .with_context(|| format!("failed to pin the runner on NUMA node {}", cli.client_node))?;
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .with_context(|| "failed to build the async runtime")?
+        .block_on(run(cli))
+}
@@ -0,0 +1,432 @@
+//! Copying snapshot files over to the devices under test (a port of `scripts/cp_2M`, along with a
+//! few alternative strategies), verified through a checksum.
+
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Read},
+    os::unix::{
+        fs::{OpenOptionsExt, PermissionsExt},
+        io::AsRawFd,
+    },
+    path::{Path, PathBuf},
+    ptr,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+
+use fbpml::cache;
+
+use crate::Device;
+
+/// The size of the chunks in which files are copied (see `scripts/cp_2M`).
+const TWO_MEBIBYTES: usize = 1 << 21;
+/// The alignment of the length of `O_DIRECT` writes; a multiple of any device's logical block.
+const DIRECT_ALIGN: usize = 1 << 12;
+
+/// The ways in which a file can be copied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// `read(2)`/`write(2)` through a 2MiB anonymous mapping, as `cp_2M` does.
+    Buffered,
+    /// Like [`Strategy::Buffered`], but writing the copy through `O_DIRECT`, bypassing the page
+    /// cache; the source is read as usual, since it may well live on a filesystem without
+    /// `O_DIRECT` support (e.g., tmpfs).
+    Direct,
+    /// In-kernel copying through `copy_file_range(2)`.
+    CopyFileRange,
+    /// `memcpy(3)` between two shared mappings; the destination's is a `MAP_SYNC` one, hence it
+    /// is only supported on DAX mounts.
+    MmapSync,
+}
+
+impl Strategy {
+    /// The strategy for staging snapshots on the given class of device.
+    ///
+    /// DCPM is expected to be mounted with DAX (see [`copy`] for what happens otherwise), while
+    /// the page cache is bypassed on all other devices, since it would be dropped before each run
+    /// anyway.
+    pub fn for_device(device: Device) -> Self {
+        match device {
+            Device::Dcpm => Self::MmapSync,
+            Device::Nvme | Device::Ssd => Self::Direct,
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "buffered" => Ok(Self::Buffered),
+            "direct" => Ok(Self::Direct),
+            "copy-file-range" => Ok(Self::CopyFileRange),
+            "mmap-sync" => Ok(Self::MmapSync),
+            _ => Err(format!(
+                "unknown strategy '{s}' \
+                 (expected one of: buffered, direct, copy-file-range, mmap-sync)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Buffered => "buffered",
+            Self::Direct => "direct",
+            Self::CopyFileRange => "copy-file-range",
+            Self::MmapSync => "mmap-sync",
+        })
+    }
+}
+
+/// The outcome of copying a file.
+#[derive(Debug, Clone, Copy)]
+pub struct CopyReport {
+    /// The strategy that was eventually used.
+    pub strategy: Strategy,
+    /// The size of the file.
+    pub bytes: u64,
+    /// The time it took to copy the file, including flushing it to the device.
+    pub elapsed: Duration,
+    /// The CRC32 of both the source and the destination file, if the copy was verified.
+    pub checksum: Option<u32>,
+}
+
+impl fmt::Display for CopyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mib = self.bytes as f64 / (1 << 20) as f64;
+        write!(
+            f,
+            "{mib:.1} MiB in {:.3}s ({:.1} MiB/s, {})",
+            self.elapsed.as_secs_f64(),
+            mib / self.elapsed.as_secs_f64(),
+            self.strategy,
+        )?;
+        if let Some(checksum) = self.checksum {
+            write!(f, "; verified (crc32: {checksum:08x})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Copy `src` to `dst` (which is created, or truncated if it already exists, with the permissions
+/// of `src`) with the given strategy, and then optionally verify the copy.
+///
+/// If [`Strategy::MmapSync`] turns out to be unsupported for `dst` (i.e., it is not on a DAX
+/// mount), [`Strategy::Buffered`] is used instead, with a warning.
+///
+/// Verification compares the checksums of the two files (see [`verify`]).
+pub fn copy(strategy: Strategy, src: &Path, dst: &Path, verify: bool) -> Result<CopyReport> {
+    let ctx = || format!("failed to copy '{}' to '{}'", src.display(), dst.display());
+
+    let (strategy, bytes, elapsed) = match copy_with(strategy, src, dst) {
+        Err(err) if strategy == Strategy::MmapSync && err.kind() == io::ErrorKind::Unsupported => {
+            eprintln!(
+                "WARNING: '{}' is not on a DAX mount; falling back to {} copying.",
+                dst.display(),
+                Strategy::Buffered
+            );
+            let (bytes, elapsed) = copy_with(Strategy::Buffered, src, dst).with_context(ctx)?;
+            (Strategy::Buffered, bytes, elapsed)
+        }
+        res => {
+            let (bytes, elapsed) = res.with_context(ctx)?;
+            (strategy, bytes, elapsed)
+        }
+    };
+
+    let checksum = if verify {
+        let expected = checksum(src)?;
+        self::verify(dst, expected)?;
+        Some(expected)
+    } else {
+        None
+    };
+
+    Ok(CopyReport {
+        strategy,
+        bytes,
+        elapsed,
+        checksum,
+    })
+}
+
+/// Make sure that the CRC32 of the file at `path` is the `expected` one, after evicting it from the
+/// page cache, so that it is actually read back from the device.
+pub fn verify(path: &Path, expected: u32) -> Result<()> {
+    cache::evict(path)?;
+    let found = checksum(path)?;
+    if found != expected {
+        bail!(
+            "'{}' is corrupted (crc32: {found:08x}; expected {expected:08x})",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// The CRC32 of the file at `path`.
+pub fn checksum(path: &Path) -> Result<u32> {
+    let ctx = || format!("failed to checksum '{}'", path.display());
+    let mut file = File::open(path).with_context(ctx)?;
+    let mut buf = Mapping::anonymous(TWO_MEBIBYTES).with_context(ctx)?;
+    let mut hasher = crc32fast::Hasher::new();
+    loop {
+        match file.read(buf.as_mut_slice()) {
+            Ok(0) => break,
+            Ok(nr) => hasher.update(&buf.as_slice()[..nr]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err).with_context(ctx),
+        }
+    }
+    Ok(hasher.finalize())
+}
+
+/// Copy `src` to `dst` with the given strategy, returning the number of bytes copied and the time
+/// it took.
+fn copy_with(strategy: Strategy, src: &Path, dst: &Path) -> io::Result<(u64, Duration)> {
+    let direct = strategy == Strategy::Direct;
+    let src_file = File::open(src)?;
+    let len = src_file.metadata()?.len();
+    let dst_file = OpenOptions::new()
+        .read(strategy == Strategy::MmapSync)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(src_file.metadata()?.permissions().mode())
+        .custom_flags(if direct { libc::O_DIRECT } else { 0 })
+        .open(dst)?;
+
+    let start = Instant::now();
+    match strategy {
+        Strategy::Buffered | Strategy::Direct => copy_buffered(&src_file, &dst_file, len, direct)?,
+        Strategy::CopyFileRange => copy_file_range(&src_file, &dst_file, len)?,
+        Strategy::MmapSync => copy_mmap_sync(&src_file, &dst_file, len)?,
+    }
+    Ok((len, start.elapsed()))
+}
+
+/// Copy through a 2MiB buffer; in case of `O_DIRECT`, the last chunk is padded to the required
+/// alignment and the excess is truncated afterwards.
+fn copy_buffered(src: &File, dst: &File, len: u64, direct: bool) -> io::Result<()> {
+    let mut buf = Mapping::anonymous(TWO_MEBIBYTES)?;
+    loop {
+        // Only the last chunk may be short, so that all `O_DIRECT` writes remain aligned
+        let nr = read_full(src, buf.as_mut_slice())?;
+        if nr == 0 {
+            break;
+        }
+        let nw = if direct {
+            let padded = nr.next_multiple_of(DIRECT_ALIGN);
+            buf.as_mut_slice()[nr..padded].fill(0);
+            padded
+        } else {
+            nr
+        };
+        write_all(dst, &buf.as_slice()[..nw])?;
+    }
+    if direct {
+        dst.set_len(len)?;
+    }
+    dst.sync_all()
+}
+
+/// Read from `file` until `buf` is full or the end of the file is reached, returning the number of
+/// bytes read.
+fn read_full(mut file: &File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut nr = 0;
+    while nr < buf.len() {
+        match file.read(&mut buf[nr..]) {
+            Ok(0) => break,
+            Ok(n) => nr += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(nr)
+}
+
+/// `std::io::Write::write_all` for a shared reference to a file, which is all `O_DIRECT` needs.
+fn write_all(mut file: &File, mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        match io::Write::write(&mut file, buf) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(nw) => buf = &buf[nw..],
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Copy in the kernel, through `copy_file_range(2)`.
+fn copy_file_range(src: &File, dst: &File, len: u64) -> io::Result<()> {
+    let mut remaining = len;
+    while remaining > 0 {
+        // SAFETY: Both file descriptors remain open for the duration of the call, and both
+        // offsets are null, so that the files' own offsets are used (and updated).
+        let ret = unsafe {
+            libc::copy_file_range(
+                src.as_raw_fd(),
+                ptr::null_mut(),
+                dst.as_raw_fd(),
+                ptr::null_mut(),
+                remaining.min(1 << 30) as usize,
+                0,
+            )
+        };
+        match ret {
+            -1 => match io::Error::last_os_error() {
+                err if err.kind() == io::ErrorKind::Interrupted => continue,
+                err => return Err(err),
+            },
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => remaining -= n as u64,
+        }
+    }
+    dst.sync_all()
+}
+
+/// Copy between a shared mapping of `src` and a `MAP_SYNC` mapping of `dst`, in 2MiB chunks.
+fn copy_mmap_sync(src: &File, dst: &File, len: u64) -> io::Result<()> {
+    dst.set_len(len)?;
+    if len == 0 {
+        return Ok(());
+    }
+    let len = len as usize;
+    let src_map = Mapping::file(src, len, libc::PROT_READ, libc::MAP_SHARED)?;
+    let mut dst_map = Mapping::file(
+        dst,
+        len,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_SHARED_VALIDATE | libc::MAP_SYNC,
+    )?;
+    for (from, to) in src_map
+        .as_slice()
+        .chunks(TWO_MEBIBYTES)
+        .zip(dst_map.as_mut_slice().chunks_mut(TWO_MEBIBYTES))
+    {
+        to.copy_from_slice(from);
+    }
+    dst_map.sync()
+}
+
+/// A memory mapping, unmapped when dropped.
+struct Mapping {
+    addr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mapping {
+    /// A private anonymous mapping, to be used as a (page-aligned) buffer.
+    fn anonymous(len: usize) -> io::Result<Self> {
+        Self::new(
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+        )
+    }
+
+    /// A mapping of the first `len` bytes of `file`.
+    fn file(file: &File, len: usize, prot: i32, flags: i32) -> io::Result<Self> {
+        Self::new(len, prot, flags, file.as_raw_fd())
+    }
+
+    fn new(len: usize, prot: i32, flags: i32, fd: i32) -> io::Result<Self> {
+        // SAFETY: A fresh mapping is requested; it does not alias any existing memory.
+        let addr = unsafe { libc::mmap(ptr::null_mut(), len, prot, flags, fd, 0) };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { addr, len })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        // SAFETY: The mapping is readable and spans `len` bytes, for as long as `self` lives.
+        unsafe { std::slice::from_raw_parts(self.addr as *const u8, self.len) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: The mapping is writable (where this is called) and spans `len` bytes, for as
+        // long as `self` lives; `&mut self` guarantees exclusive access.
+        unsafe { std::slice::from_raw_parts_mut(self.addr as *mut u8, self.len) }
+    }
+
+    /// Flush the mapping to the underlying file.
+    fn sync(&self) -> io::Result<()> {
+        // SAFETY: `addr` is page-aligned and mapped for `len` bytes.
+        match unsafe { libc::msync(self.addr, self.len, libc::MS_SYNC) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: `addr` was mapped for `len` bytes and is not accessed after `self` is dropped.
+        unsafe { libc::munmap(self.addr, self.len) };
+    }
+}
+
+/// Copy a (snapshot) file with any of the supported strategies, reporting the throughput achieved
+/// and verifying the copy through a checksum.
+#[derive(clap::Args)]
+pub struct SnapcopyCmd {
+    /// How to copy the file; one of 'buffered' (`cp_2M`), 'direct' (`O_DIRECT`),
+    /// 'copy-file-range' or 'mmap-sync' (DAX mounts only).
+    #[clap(short = 's', long = "strategy", default_value = "buffered")]
+    strategy: Strategy,
+
+    /// Overwrite the destination file, if it already exists.
+    #[clap(short = 'f', long = "force")]
+    force: bool,
+
+    /// Do not verify the copy.
+    #[clap(long = "no-verify")]
+    no_verify: bool,
+
+    /// The file to copy.
+    src: PathBuf,
+
+    /// The path of the copy.
+    dst: PathBuf,
+}
+
+impl SnapcopyCmd {
+    pub fn run(&self) -> Result<()> {
+        if !self.force && self.dst.exists() {
+            bail!("File '{}' already exists!", self.dst.display());
+        }
+        println!("'{}' --> '{}'", self.src.display(), self.dst.display());
+        let report = copy(self.strategy, &self.src, &self.dst, !self.no_verify)?;
+        println!("{report}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_strategies() {
+        for strategy in [
+            Strategy::Buffered,
+            Strategy::Direct,
+            Strategy::CopyFileRange,
+            Strategy::MmapSync,
+        ] {
+            assert_eq!(strategy.to_string().parse(), Ok(strategy));
+        }
+        for s in ["", "cp_2M", "Direct", "copy_file_range"] {
+            assert!(s.parse::<Strategy>().is_err(), "{s}");
+        }
+    }
+}
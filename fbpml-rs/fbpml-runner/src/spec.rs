@@ -0,0 +1,629 @@
+//! Declarative experiment specifications, expanded into a matrix of cells that can be run (and
+//! resumed) one after the other.
+//!
+//! An experiment is described by a TOML file such as:
+//!
+//! ```toml
+//! outdir = "/nvme/ckatsak/fbpml_outdir_sweep"  # optional; `--outdir` takes precedence
+//! snapshot_dir = "snapshot"                    # optional; `--snapshot-dir` otherwise
+//! benchmarks = ["chameleon", "matmul_fb"]
+//! num_uvms = [1, 16, 32]
+//! mem_mib = [512, 1024]                        # optional
+//! vcpus = [1, 2]                               # optional
+//! pre_warm = [0, 5]                            # optional; defaults to `[0]`
+//! snapshots = ["full", "diff1"]                # optional; defaults to `["full"]`
+//! runs = 10
+//! reuse_connection = false                     # optional
+//! sync_clock = false                           # optional
+//! timeout = 30.0                               # optional; in (fractional) seconds
+//!
+//! [devices]                                    # any of `dcpm`, `nvme` and `ssd`
+//! dcpm = "/mnt/pmem0/ckatsak/fbpml_2304Mi"
+//! nvme = "/nvme/ckatsak/fbpml_2304Mi"
+//!
+//! [args]                                       # optional; benchmarks' default arguments otherwise
+//! matmul_fb = [[512, 512], [1024, 1024]]
+//! ```
+//!
+//! Guest memory size and VCPU count are baked into the snapshots, so `build-snapshots` must have
+//! stored the snapshots of each such combination under `$SNAPSHOT_DIR/$BENCH/${MEM}MiB-${VCPUS}vcpu`
+//! (e.g., `snapshot/chameleon/1024MiB-2vcpu`); when both are omitted, the snapshots are expected
+//! right under `$SNAPSHOT_DIR/$BENCH`, as usual.
+//!
+//! Each of the `snapshots` is either the `full` one or the `K`-th of the diff snapshots created by
+//! `build-snapshots --diff-snapshots`, which are expected under the `diffK` subdirectory of the
+//! full one's.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt, fs,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use fbpml::{bench::Benchmark, multi::WorkerOpts, RpcArgs};
+
+use crate::Device;
+
+/// The name of the file (in the output directory) that records the runs completed so far.
+const PROGRESS_FILE: &str = ".done";
+/// The name of the copy of the experiment specification kept in the output directory.
+const SPEC_COPY: &str = "experiment.toml";
+
+/// An experiment specification, as read from a TOML file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Experiment {
+    /// Directory to store the results in.
+    pub outdir: Option<PathBuf>,
+    /// Directory where the snapshots of all benchmarks initially live.
+    pub snapshot_dir: Option<PathBuf>,
+    benchmarks: Vec<String>,
+    devices: Devices,
+    num_uvms: Vec<usize>,
+    #[serde(default)]
+    mem_mib: Vec<u64>,
+    #[serde(default)]
+    vcpus: Vec<u64>,
+    #[serde(default = "default_pre_warm")]
+    pre_warm: Vec<usize>,
+    #[serde(default = "default_snapshots")]
+    snapshots: Vec<SnapshotKind>,
+    runs: usize,
+    #[serde(default)]
+    reuse_connection: bool,
+    #[serde(default)]
+    sync_clock: bool,
+    timeout: Option<f64>,
+    #[serde(default)]
+    args: BTreeMap<String, Vec<Vec<Arg>>>,
+    /// The specification exactly as it was read, to be kept along with the results.
+    #[serde(skip)]
+    raw: String,
+}
+
+fn default_pre_warm() -> Vec<usize> {
+    vec![0]
+}
+
+fn default_snapshots() -> Vec<SnapshotKind> {
+    vec![SnapshotKind::Full]
+}
+
+/// The snapshot that the MicroVMs are restored from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum SnapshotKind {
+    /// The full snapshot.
+    Full,
+    /// The `K`-th diff snapshot (starting from 1), with its memory file layered on top of the full
+    /// snapshot's (see `fbpml::diff`).
+    Diff(usize),
+}
+
+impl SnapshotKind {
+    pub fn is_diff(self) -> bool {
+        matches!(self, Self::Diff(_))
+    }
+}
+
+impl FromStr for SnapshotKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Self::Full),
+            _ => match s.strip_prefix("diff").and_then(|k| k.parse().ok()) {
+                Some(k) if k > 0 => Ok(Self::Diff(k)),
+                _ => Err(format!(
+                    "unknown snapshot '{s}' (expected 'full' or 'diffK', for some K > 0)"
+                )),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for SnapshotKind {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for SnapshotKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full => f.write_str("full"),
+            Self::Diff(k) => write!(f, "diff{k}"),
+        }
+    }
+}
+
+/// The directories where snapshots are staged on each device under test.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Devices {
+    dcpm: Option<PathBuf>,
+    nvme: Option<PathBuf>,
+    ssd: Option<PathBuf>,
+}
+
+/// A benchmark argument, which may be written in TOML either as an integer or as a string.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Arg {
+    Int(u64),
+    Str(String),
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(n) => write!(f, "{n}"),
+            Self::Str(s) => f.write_str(s),
+        }
+    }
+}
+
+impl Experiment {
+    /// Read and parse the experiment specification in `path`.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let raw = fs::read_to_string(path)
+            .with_context(|| format!("failed to read experiment '{}'", path.display()))?;
+        let mut experiment: Self = toml::from_str(&raw)
+            .with_context(|| format!("failed to parse experiment '{}'", path.display()))?;
+        experiment.raw = raw;
+        Ok(experiment)
+    }
+
+    /// Expand the experiment into the matrix of its cells, storing their results under `outdir`.
+    ///
+    /// All benchmark names and arguments are validated beforehand, so that a typo does not get to
+    /// abort a sweep halfway through.
+    pub fn cells(&self, outdir: &Path) -> Result<Vec<Cell>> {
+        if self.benchmarks.is_empty()
+            || self.num_uvms.is_empty()
+            || self.pre_warm.is_empty()
+            || self.snapshots.is_empty()
+        {
+            bail!("'benchmarks', 'num_uvms', 'pre_warm' and 'snapshots' must not be empty");
+        }
+        if self.num_uvms.contains(&0) {
+            bail!("'num_uvms' must only contain positive numbers");
+        }
+        let devices: Vec<_> = [
+            (Device::Dcpm, &self.devices.dcpm),
+            (Device::Nvme, &self.devices.nvme),
+            (Device::Ssd, &self.devices.ssd),
+        ]
+        .into_iter()
+        .filter_map(|(device, path)| path.clone().map(|path| (device, path)))
+        .collect();
+        if devices.is_empty() {
+            bail!("no device was specified");
+        }
+        let benches = self
+            .benchmarks
+            .iter()
+            .map(|name| Benchmark::lookup(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        for name in self.args.keys() {
+            let bench = Benchmark::lookup(name)?;
+            if !benches.contains(&bench) {
+                bail!("arguments were specified for '{name}', which is not in 'benchmarks'");
+            }
+        }
+        let timeout = self
+            .timeout
+            .map(|secs| {
+                Duration::try_from_secs_f64(secs)
+                    .ok()
+                    .filter(|timeout| !timeout.is_zero())
+                    .with_context(|| format!("invalid timeout '{secs}'"))
+            })
+            .transpose()?;
+        let mem_mib = optional(&self.mem_mib);
+        let vcpus = optional(&self.vcpus);
+
+        let mut cells = Vec::new();
+        for &bench in &benches {
+            let arg_sets: Vec<Vec<String>> = match self.args.get(bench.name).or_else(|| {
+                // Also accept the benchmark's name as spelled on the command line
+                self.args.get(&bench.cli_name())
+            }) {
+                Some(sets) => sets
+                    .iter()
+                    .map(|set| set.iter().map(ToString::to_string).collect())
+                    .collect(),
+                None => vec![bench.default_args.iter().map(ToString::to_string).collect()],
+            };
+            for args in &arg_sets {
+                let rpc_args = bench.rpc_args(args)?;
+                for &mem_mib in &mem_mib {
+                    for &vcpus in &vcpus {
+                        for &snapshot in &self.snapshots {
+                            for (device, path) in &devices {
+                                for &num_uvms in &self.num_uvms {
+                                    for &pre_warm in &self.pre_warm {
+                                        let mut cell = Cell {
+                                            bench,
+                                            rpc_args: rpc_args.clone(),
+                                            device: *device,
+                                            device_path: path.clone(),
+                                            num_uvms,
+                                            mem_mib,
+                                            vcpus,
+                                            snapshot,
+                                            opts: WorkerOpts {
+                                                pre_warm,
+                                                reuse_connection: self.reuse_connection,
+                                                sync_clock: self.sync_clock,
+                                                timeout,
+                                            },
+                                            runs: self.runs,
+                                            results: PathBuf::new(),
+                                        };
+                                        cell.results = outdir
+                                            .join(bench.name)
+                                            .join(device.name())
+                                            .join(cell.label(args));
+                                        cells.push(cell);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // Cells that would share their results would also share their progress, silently skipping
+        // or overwriting each other's runs
+        let mut seen = HashSet::new();
+        if let Some(cell) = cells.iter().find(|cell| !seen.insert(&cell.results)) {
+            bail!(
+                "more than one cell would store its results in '{}'; are there duplicate values?",
+                cell.results.display()
+            );
+        }
+        Ok(cells)
+    }
+
+    /// Keep a copy of the specification in `outdir`, along with the results.
+    ///
+    /// If `outdir` already holds a copy of another specification (i.e., an experiment is resumed
+    /// after its specification was edited), it is only replaced if `overwrite` is set, since the
+    /// results of both would then be mixed up under it.
+    pub fn save(&self, outdir: &Path, overwrite: bool) -> Result<()> {
+        let path = outdir.join(SPEC_COPY);
+        match fs::read_to_string(&path) {
+            Ok(saved) if saved == self.raw => return Ok(()),
+            Ok(_) if !overwrite => bail!(
+                "'{}' holds another experiment specification; pass `--overwrite-spec` to resume \
+                 the experiment with this one anyway",
+                path.display()
+            ),
+            Ok(_) => (),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read '{}'", path.display()))
+            }
+        }
+        fs::write(&path, &self.raw).with_context(|| format!("failed to write '{}'", path.display()))
+    }
+}
+
+/// The values of an optional dimension of the matrix; `None` stands for "whatever the snapshots
+/// were built with".
+fn optional(values: &[u64]) -> Vec<Option<u64>> {
+    match values {
+        [] => vec![None],
+        values => values.iter().copied().map(Some).collect(),
+    }
+}
+
+/// A single cell of the experiment's matrix: a benchmark, issued with a specific set of arguments
+/// to a number of MicroVMs of a specific size, restored from a specific (full or diff) snapshot on
+/// a specific device.
+#[derive(Debug, Clone)]
+pub struct Cell {
+    pub bench: &'static Benchmark,
+    pub rpc_args: RpcArgs,
+    pub device: Device,
+    /// The directory where the snapshots are staged on `device`.
+    pub device_path: PathBuf,
+    pub num_uvms: usize,
+    pub mem_mib: Option<u64>,
+    pub vcpus: Option<u64>,
+    pub snapshot: SnapshotKind,
+    pub opts: WorkerOpts,
+    /// Number of runs.
+    pub runs: usize,
+    /// The directory where the results of all runs are stored (as `runNN.csv`).
+    pub results: PathBuf,
+}
+
+impl Cell {
+    /// The path of the directory of the snapshots of this cell, relative to the snapshot directory
+    /// (or to the device's staging directory).
+    pub fn snapshot_subdir(&self) -> PathBuf {
+        let dir = PathBuf::from(self.bench.name);
+        let dir = match (self.mem_mib, self.vcpus) {
+            (None, None) => dir,
+            (mem_mib, vcpus) => dir.join(format!(
+                "{}MiB-{}vcpu",
+                mem_mib.unwrap_or(self.bench.mem_size_mib),
+                vcpus.unwrap_or(1),
+            )),
+        };
+        match self.snapshot {
+            SnapshotKind::Full => dir,
+            diff => dir.join(diff.to_string()),
+        }
+    }
+
+    /// The name of the cell's results directory, which captures all of its parameters except for
+    /// the benchmark and the device.
+    fn label(&self, args: &[String]) -> String {
+        let mut label = format!("uvms{}", self.num_uvms);
+        if let Some(mem_mib) = self.mem_mib {
+            label.push_str(&format!("_mem{mem_mib}"));
+        }
+        if let Some(vcpus) = self.vcpus {
+            label.push_str(&format!("_vcpus{vcpus}"));
+        }
+        label.push_str(&format!("_prewarm{}", self.opts.pre_warm));
+        if self.snapshot.is_diff() {
+            label.push_str(&format!("_{}", self.snapshot));
+        }
+        if !args.is_empty() {
+            let sanitized: Vec<String> = args
+                .iter()
+                .map(|arg| {
+                    arg.chars()
+                        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+                        .collect()
+                })
+                .collect();
+            label.push_str(&format!("_args{}", sanitized.join("-")));
+            // Sanitizing (and joining) may map different arguments to the same label (e.g., `1.5`
+            // and `1-5`, or `a-b` and `a`, `b`), in which case it is disambiguated by a checksum
+            // of the arguments as they were given
+            if args
+                .iter()
+                .any(|arg| !arg.chars().all(|c| c.is_ascii_alphanumeric()))
+            {
+                label.push_str(&format!("_{:08x}", args_checksum(args)));
+            }
+        }
+        label
+    }
+}
+
+/// A checksum of `args` that tells apart any two different sets of arguments in practice; unlike
+/// `std`'s hashers, it is stable, so that the labels of a resumed experiment's cells do not change.
+fn args_checksum(args: &[String]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for arg in args {
+        hasher.update(&(arg.len() as u64).to_le_bytes());
+        hasher.update(arg.as_bytes());
+    }
+    hasher.finalize()
+}
+
+/// The record of the runs of an experiment that have been completed, so that an interrupted
+/// experiment can be resumed from where it stopped.
+///
+/// It is an append-only file in the output directory, with one line per completed run (i.e., the
+/// path of its results, relative to the output directory).
+pub struct Progress {
+    outdir: PathBuf,
+    done: HashSet<String>,
+    file: File,
+}
+
+impl Progress {
+    /// Open (or create) the record of the completed runs in `outdir`.
+    pub fn open(outdir: &Path) -> Result<Self> {
+        let path = outdir.join(PROGRESS_FILE);
+        let ctx = || format!("failed to open '{}'", path.display());
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)
+            .with_context(ctx)?;
+        let done = BufReader::new(&file)
+            .lines()
+            .collect::<Result<_, _>>()
+            .with_context(ctx)?;
+        Ok(Self {
+            outdir: outdir.to_path_buf(),
+            done,
+            file,
+        })
+    }
+
+    fn key(&self, outfile: &Path) -> String {
+        outfile
+            .strip_prefix(&self.outdir)
+            .unwrap_or(outfile)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Whether the run whose results are stored in `outfile` has been completed.
+    pub fn is_done(&self, outfile: &Path) -> bool {
+        self.done.contains(&self.key(outfile))
+    }
+
+    /// Record that the run whose results are stored in `outfile` has been completed.
+    pub fn mark_done(&mut self, outfile: &Path) -> Result<()> {
+        let key = self.key(outfile);
+        writeln!(self.file, "{key}")
+            .and_then(|_| self.file.sync_data())
+            .with_context(|| format!("failed to record the completion of '{key}'"))?;
+        self.done.insert(key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn experiment(spec: &str) -> Experiment {
+        let mut experiment: Experiment = toml::from_str(spec).unwrap();
+        experiment.raw = spec.to_string();
+        experiment
+    }
+
+    fn labels(cells: &[Cell]) -> Vec<String> {
+        cells
+            .iter()
+            .map(|cell| cell.results.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn expands_the_matrix_in_order() {
+        let experiment = experiment(
+            r#"
+            benchmarks = ["chameleon", "helloworld"]
+            num_uvms = [1, 16]
+            snapshots = ["full", "diff2"]
+            runs = 3
+            [devices]
+            dcpm = "/pmem"
+            nvme = "/nvme"
+            [args]
+            chameleon = [[10, 15], [20, 30]]
+            "#,
+        );
+        let cells = experiment.cells(Path::new("out")).unwrap();
+        assert_eq!(cells.len(), 2 * 2 * 2 * 2 + 2 * 2 * 2);
+        assert!(cells.iter().all(|cell| cell.runs == 3));
+        assert_eq!(
+            labels(&cells[..4]),
+            [
+                "out/chameleon/dcpm/uvms1_prewarm0_args10-15",
+                "out/chameleon/dcpm/uvms16_prewarm0_args10-15",
+                "out/chameleon/nvme/uvms1_prewarm0_args10-15",
+                "out/chameleon/nvme/uvms16_prewarm0_args10-15",
+            ]
+        );
+        assert_eq!(
+            cells[4].results,
+            Path::new("out/chameleon/dcpm/uvms1_prewarm0_diff2_args10-15")
+        );
+        assert_eq!(cells[4].device_path, Path::new("/pmem"));
+        assert_eq!(
+            cells[4].snapshot_subdir(),
+            Path::new("chameleon").join("diff2")
+        );
+        assert_eq!(
+            cells[8].results,
+            Path::new("out/chameleon/dcpm/uvms1_prewarm0_args20-30")
+        );
+        assert_eq!(
+            cells.last().unwrap().results,
+            Path::new("out/helloworld/nvme/uvms16_prewarm0_diff2")
+        );
+    }
+
+    #[test]
+    fn labels_optional_dimensions() {
+        let experiment = experiment(
+            r#"
+            benchmarks = ["matmul_fb"]
+            num_uvms = [4]
+            mem_mib = [1024]
+            vcpus = [2]
+            pre_warm = [5]
+            runs = 1
+            [devices]
+            ssd = "/ssd"
+            "#,
+        );
+        let cells = experiment.cells(Path::new("out")).unwrap();
+        assert_eq!(
+            labels(&cells),
+            ["out/matmul_fb/ssd/uvms4_mem1024_vcpus2_prewarm5_args512-512"]
+        );
+        assert_eq!(
+            cells[0].snapshot_subdir(),
+            Path::new("matmul_fb").join("1024MiB-2vcpu")
+        );
+    }
+
+    #[test]
+    fn labels_of_sanitized_args_do_not_collide() {
+        let experiment = experiment(
+            r#"
+            benchmarks = ["rnn_serving"]
+            num_uvms = [1]
+            runs = 1
+            [devices]
+            nvme = "/nvme"
+            [args]
+            rnn_serving = [["Old-English", 16], ["Old.English", 16], ["Old English", 16]]
+            "#,
+        );
+        let cells = experiment.cells(Path::new("out")).unwrap();
+        let labels = labels(&cells);
+        assert_eq!(labels.iter().collect::<HashSet<_>>().len(), 3);
+        assert!(labels
+            .iter()
+            .all(|label| label.contains("_argsOld-English-16_")));
+        let again = experiment.cells(Path::new("out")).unwrap();
+        assert_eq!(labels, self::labels(&again), "labels must be stable");
+    }
+
+    #[test]
+    fn rejects_duplicate_cells() {
+        let experiment = experiment(
+            r#"
+            benchmarks = ["helloworld"]
+            num_uvms = [1, 1]
+            runs = 1
+            [devices]
+            nvme = "/nvme"
+            "#,
+        );
+        assert!(experiment.cells(Path::new("out")).is_err());
+    }
+
+    #[test]
+    fn refuses_to_resume_with_another_spec() {
+        let outdir = env::temp_dir().join(format!("fbpml-runner-spec-{}", std::process::id()));
+        fs::create_dir_all(&outdir).unwrap();
+        let spec =
+            "benchmarks = [\"helloworld\"]\nnum_uvms = [1]\nruns = 1\n[devices]\nssd = \"/ssd\"\n";
+        experiment(spec).save(&outdir, false).unwrap();
+        experiment(spec).save(&outdir, false).unwrap();
+        let edited = spec.replace("runs = 1", "runs = 2");
+        assert!(experiment(&edited).save(&outdir, false).is_err());
+        experiment(&edited).save(&outdir, true).unwrap();
+        assert_eq!(fs::read_to_string(outdir.join(SPEC_COPY)).unwrap(), edited);
+        fs::remove_dir_all(&outdir).unwrap();
+    }
+
+    #[test]
+    fn parses_snapshot_kinds() {
+        for s in ["full", "diff1", "diff12"] {
+            assert_eq!(s.parse::<SnapshotKind>().unwrap().to_string(), s);
+        }
+        for s in ["", "diff", "diff0", "diff-1", "Full", "diff1x"] {
+            assert!(s.parse::<SnapshotKind>().is_err(), "{s}");
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Staging of the snapshot files on the devices under test.
+//!
+//! Each staging directory keeps a manifest (`.staged.json`) of the files that have been staged in
+//! it, along with their CRC32. A staged file is reused for as long as it has not been touched since
+//! it was staged and its source still has the same content; otherwise it is re-staged. The
+//! manifests also allow [`clean`] to remove exactly what has been staged, and nothing else.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use fbpml::bench::Benchmark;
+
+use crate::{
+    snapcopy::{self, Strategy},
+    Device,
+};
+
+/// The name of the manifest of each staging directory.
+const MANIFEST: &str = ".staged.json";
+
+/// The size and modification time of a file, to tell whether it has been touched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Stamp {
+    len: u64,
+    mtime: SystemTime,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Result<Self> {
+        let meta =
+            fs::metadata(path).with_context(|| format!("could not stat '{}'", path.display()))?;
+        Ok(Self {
+            len: meta.len(),
+            mtime: meta.modified()?,
+        })
+    }
+}
+
+/// A file that has been staged and verified.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Staged {
+    /// The CRC32 of the file's content.
+    crc32: u32,
+    /// The stamp of the source file, as of the last time its content was hashed.
+    src: Stamp,
+    /// The stamp of the staged copy, right after it was verified.
+    dst: Stamp,
+}
+
+/// The files staged in a directory, by name.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    files: BTreeMap<String, Staged>,
+}
+
+impl Manifest {
+    /// Load the manifest of `dir`; a missing manifest is an empty one.
+    fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(MANIFEST);
+        match fs::read(&path) {
+            Ok(buf) => serde_json::from_slice(&buf)
+                .with_context(|| format!("failed to parse '{}'", path.display())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("failed to read '{}'", path.display())),
+        }
+    }
+
+    /// Store the manifest of `dir`, atomically replacing the previous one.
+    fn save(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(MANIFEST);
+        let tmp = dir.join(format!("{MANIFEST}.tmp"));
+        let ctx = || format!("failed to write '{}'", path.display());
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?).with_context(ctx)?;
+        fs::rename(&tmp, &path).with_context(ctx)
+    }
+}
+
+/// Stage the snapshot (i.e., state and memory) files of MicroVMs `0..num_uvms` from `src_dir` to
+/// `dst_dir`, which resides on the given `device`.
+///
+/// Files are copied with the [`Strategy`] suited to the device and verified against the CRC32 of
+/// their source, unless a valid copy of them has already been staged.
+pub fn stage_snapshots(
+    device: Device,
+    src_dir: &Path,
+    dst_dir: &Path,
+    num_uvms: usize,
+) -> Result<()> {
+    fs::create_dir_all(dst_dir)
+        .with_context(|| format!("failed to create '{}'", dst_dir.display()))?;
+    let mut manifest = Manifest::load(dst_dir)?;
+    for id in 0..num_uvms {
+        for name in [
+            format!("snapshot-{id:02X}.file"),
+            format!("memory-{id:02X}.file"),
+        ] {
+            let staged = manifest.files.get(&name).copied();
+            if let Some(staged) = stage_file(device, src_dir, dst_dir, &name, staged)? {
+                // Record each file as soon as it is staged, so that an interruption wastes nothing
+                manifest.files.insert(name, staged);
+                manifest.save(dst_dir)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Stage `src_dir/name` as `dst_dir/name`, unless the `previous` copy is still valid; returns the
+/// new record of the file, if it has changed.
+fn stage_file(
+    device: Device,
+    src_dir: &Path,
+    dst_dir: &Path,
+    name: &str,
+    previous: Option<Staged>,
+) -> Result<Option<Staged>> {
+    let (src, dst) = (src_dir.join(name), dst_dir.join(name));
+    let src_stamp = Stamp::of(&src)?;
+
+    let mut crc32 = None;
+    if let Some(previous) = previous {
+        if Stamp::of(&dst).ok() == Some(previous.dst) {
+            if previous.src == src_stamp {
+                return Ok(None);
+            }
+            // The source has been touched, but its content may well be the same
+            let crc = snapcopy::checksum(&src)?;
+            if crc == previous.crc32 {
+                return Ok(Some(Staged {
+                    src: src_stamp,
+                    ..previous
+                }));
+            }
+            crc32 = Some(crc);
+        }
+        eprintln!("'{}' is stale; re-staging it...", dst.display());
+    }
+
+    let crc32 = match crc32 {
+        Some(crc32) => crc32,
+        None => snapcopy::checksum(&src)?,
+    };
+    eprintln!("'{}' --> '{}'", src.display(), dst.display());
+    let report = snapcopy::copy(Strategy::for_device(device), &src, &dst, false)?;
+    snapcopy::verify(&dst, crc32)?;
+    eprintln!("{report}; verified (crc32: {crc32:08x})");
+
+    Ok(Some(Staged {
+        crc32,
+        src: src_stamp,
+        dst: Stamp::of(&dst)?,
+    }))
+}
+
+/// Remove all files staged under `dir` (recursively), along with their manifests and any
+/// subdirectories left empty; returns the number of bytes freed.
+///
+/// Files that were not staged by the runner are left intact.
+pub fn clean(dir: &Path) -> Result<u64> {
+    let mut freed = 0;
+    for name in Manifest::load(dir)?.files.keys() {
+        let path = dir.join(name);
+        match fs::metadata(&path) {
+            Ok(meta) => {
+                fs::remove_file(&path)
+                    .with_context(|| format!("failed to remove '{}'", path.display()))?;
+                freed += meta.len();
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => {
+                return Err(err).with_context(|| format!("could not stat '{}'", path.display()))
+            }
+        }
+    }
+    let path = dir.join(MANIFEST);
+    if path.exists() {
+        fs::remove_file(&path).with_context(|| format!("failed to remove '{}'", path.display()))?;
+    }
+
+    for entry in fs::read_dir(dir).with_context(|| format!("failed to list '{}'", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() && !path.is_symlink() {
+            freed += clean(&path)?;
+            // Fails (harmlessly) unless it is empty
+            let _ = fs::remove_dir(&path);
+        }
+    }
+    Ok(freed)
+}
+
+/// Remove the snapshots staged on the given devices, to reclaim their space.
+#[derive(clap::Args)]
+pub struct CleanCmd {
+    /// Only remove the snapshots of the given benchmark.
+    #[clap(short = 'b', long = "bench", parse(try_from_str = Benchmark::lookup))]
+    bench: Option<&'static Benchmark>,
+
+    /// The directories where snapshots are staged on each device (i.e., as passed through
+    /// `--pmem-path`, `--nvme-path` or `--ssd-path`).
+    #[clap(required = true)]
+    paths: Vec<PathBuf>,
+}
+
+impl CleanCmd {
+    pub fn run(&self) -> Result<()> {
+        for path in &self.paths {
+            let dir = match self.bench {
+                Some(bench) => path.join(bench.name),
+                None => path.clone(),
+            };
+            if !dir.is_dir() {
+                eprintln!("Nothing is staged in '{}'.", dir.display());
+                continue;
+            }
+            let freed = clean(&dir)?;
+            println!(
+                "Freed {:.1} MiB from '{}'",
+                freed as f64 / (1 << 20) as f64,
+                dir.display()
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+//! The catalog of the benchmarks supported in fbpml.
+//!
+//! Every [`Benchmark`] describes the gRPC request it expects (i.e., its [`Arity`]), the arguments
+//! it is issued with by default, and a couple of hints for setting up its MicroVMs. The command
+//! line interfaces of all binaries derive their benchmark subcommands (see [`BenchCmd`]) and their
+//! validation from this catalog, rather than duplicating it.
+
+use std::num::ParseIntError;
+
+use clap::{Arg, ArgMatches, Command, ErrorKind, FromArgMatches, Subcommand};
+
+use crate::RpcArgs;
+
+/// The shape of a benchmark's gRPC request, which also determines the service it is served by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    /// Served by the `ZeroArguments` service.
+    Zero,
+    /// Served by the `OneArgument` service.
+    One,
+    /// Served by the `TwoArguments` service.
+    Two,
+    /// Served by the `StringArgument` service.
+    Str,
+}
+
+impl Arity {
+    /// The number of arguments a request of this `Arity` consists of.
+    pub fn len(self) -> usize {
+        match self {
+            Self::Zero => 0,
+            Self::One => 1,
+            Self::Two | Self::Str => 2,
+        }
+    }
+
+    /// Whether requests of this `Arity` carry no arguments at all.
+    pub fn is_empty(self) -> bool {
+        self == Self::Zero
+    }
+}
+
+/// A benchmark supported in fbpml.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Benchmark {
+    /// The name of the benchmark, as used for its directories and files (e.g., `rnn_serving`).
+    pub name: &'static str,
+    /// A short description of the benchmark's workload.
+    pub about: &'static str,
+    /// The shape of the benchmark's gRPC request.
+    pub arity: Arity,
+    /// The names of the request's arguments; as many as `arity.len()`.
+    pub params: &'static [&'static str],
+    /// The arguments the benchmark is issued with by default; as many as `arity.len()`.
+    pub default_args: &'static [&'static str],
+    /// The suggested guest memory size (in MiB) for the benchmark's MicroVMs.
+    pub mem_size_mib: u64,
+    /// Whether the benchmark fetches its input from the MinIO server.
+    pub needs_minio: bool,
+}
+
+/// All benchmarks supported in fbpml, sorted by name.
+pub const BENCHMARKS: &[Benchmark] = &[
+    Benchmark {
+        name: "chameleon",
+        about: "Render an HTML table of the given number of rows and columns",
+        arity: Arity::Two,
+        params: &["rows", "cols"],
+        default_args: &["10", "15"],
+        mem_size_mib: 512,
+        needs_minio: false,
+    },
+    Benchmark {
+        name: "cnn_serving",
+        about: "Classify one of the bundled images with a CNN",
+        arity: Arity::One,
+        params: &["image"],
+        default_args: &["0"],
+        mem_size_mib: 512,
+        needs_minio: false,
+    },
+    Benchmark {
+        name: "helloworld",
+        about: "Return right away",
+        arity: Arity::Zero,
+        params: &[],
+        default_args: &[],
+        mem_size_mib: 512,
+        needs_minio: false,
+    },
+    Benchmark {
+        name: "image_rotate",
+        about: "Fetch one of the input images from MinIO and rotate it",
+        arity: Arity::One,
+        params: &["image"],
+        default_args: &["2"],
+        mem_size_mib: 512,
+        needs_minio: true,
+    },
+    Benchmark {
+        name: "json_serdes",
+        about: "Fetch one of the input JSON files from MinIO and (de)serialize it",
+        arity: Arity::One,
+        params: &["input"],
+        default_args: &["0"],
+        mem_size_mib: 512,
+        needs_minio: true,
+    },
+    Benchmark {
+        name: "lr_serving",
+        about: "Serve a logistic regression model on one of the bundled datasets",
+        arity: Arity::One,
+        params: &["dataset"],
+        default_args: &["0"],
+        mem_size_mib: 512,
+        needs_minio: false,
+    },
+    Benchmark {
+        name: "lr_training",
+        about: "Fetch one of the datasets from MinIO and train a logistic regression model on it",
+        arity: Arity::One,
+        params: &["dataset"],
+        default_args: &["0"],
+        mem_size_mib: 512,
+        needs_minio: true,
+    },
+    Benchmark {
+        name: "matmul_fb",
+        about: "Multiply two random matrices of the given dimensions (FunctionBench's variant)",
+        arity: Arity::Two,
+        params: &["n", "m"],
+        default_args: &["512", "512"],
+        mem_size_mib: 512,
+        needs_minio: false,
+    },
+    Benchmark {
+        name: "matmul_fbpml",
+        about: "Multiply two fixed-size random matrices",
+        arity: Arity::Zero,
+        params: &[],
+        default_args: &[],
+        mem_size_mib: 512,
+        needs_minio: false,
+    },
+    Benchmark {
+        name: "pyaes",
+        about: "Encrypt and decrypt a fixed message with AES",
+        arity: Arity::Zero,
+        params: &[],
+        default_args: &[],
+        mem_size_mib: 512,
+        needs_minio: false,
+    },
+    Benchmark {
+        name: "rnn_serving",
+        about: "Generate names in the given language with an RNN, for as many start letters",
+        arity: Arity::Str,
+        params: &["language", "length"],
+        default_args: &["Scottish", "16"],
+        mem_size_mib: 512,
+        needs_minio: false,
+    },
+    Benchmark {
+        name: "video_processing",
+        about: "Fetch one of the input videos from MinIO and convert it to grayscale",
+        arity: Arity::One,
+        params: &["video"],
+        default_args: &["0"],
+        mem_size_mib: 512,
+        needs_minio: true,
+    },
+];
+
+/// Errors that may occur while looking up a [`Benchmark`] or parsing its arguments.
+#[derive(Debug, thiserror::Error)]
+pub enum BenchError {
+    /// There is no benchmark with the given name.
+    #[error("unknown benchmark '{0}'")]
+    Unknown(String),
+
+    /// The number of arguments does not match the benchmark's [`Arity`].
+    #[error("benchmark '{bench}' expects {expected} argument(s), but {given} were given")]
+    ArgCount {
+        bench: &'static str,
+        expected: usize,
+        given: usize,
+    },
+
+    /// An argument that should be an unsigned integer could not be parsed as such.
+    #[error("invalid argument '{param}' for benchmark '{bench}': {source}")]
+    InvalidArg {
+        bench: &'static str,
+        param: &'static str,
+        #[source]
+        source: ParseIntError,
+    },
+}
+
+impl Benchmark {
+    /// Look up the benchmark with the given name; both `_` and `-` are accepted as separators
+    /// (e.g., `rnn_serving` and `rnn-serving`).
+    pub fn lookup(name: &str) -> Result<&'static Self, BenchError> {
+        BENCHMARKS
+            .iter()
+            .find(|b| b.name.replace('_', "-") == name.replace('_', "-"))
+            .ok_or_else(|| BenchError::Unknown(name.to_owned()))
+    }
+
+    /// The name of the benchmark's subcommand (i.e., with all `_` replaced by `-`).
+    pub fn cli_name(&self) -> String {
+        self.name.replace('_', "-")
+    }
+
+    /// Parse the given arguments into the benchmark's gRPC request arguments.
+    pub fn rpc_args(&self, args: &[impl AsRef<str>]) -> Result<RpcArgs, BenchError> {
+        if args.len() != self.arity.len() {
+            return Err(BenchError::ArgCount {
+                bench: self.name,
+                expected: self.arity.len(),
+                given: args.len(),
+            });
+        }
+        let int = |i: usize| {
+            args[i]
+                .as_ref()
+                .parse::<u64>()
+                .map_err(|source| BenchError::InvalidArg {
+                    bench: self.name,
+                    param: self.params[i],
+                    source,
+                })
+        };
+        Ok(match self.arity {
+            Arity::Zero => RpcArgs::Zero,
+            Arity::One => RpcArgs::One(int(0)?),
+            Arity::Two => RpcArgs::Two(int(0)?, int(1)?),
+            Arity::Str => RpcArgs::Str(args[0].as_ref().to_owned(), int(1)?),
+        })
+    }
+
+    /// The benchmark's gRPC request arguments, when issued with its default arguments.
+    pub fn default_rpc_args(&self) -> RpcArgs {
+        self.rpc_args(self.default_args)
+            .expect("invalid default arguments in the benchmark catalog")
+    }
+
+    /// Whether the `i`-th argument is an unsigned integer (rather than a string).
+    fn is_int_param(&self, i: usize) -> bool {
+        !(self.arity == Arity::Str && i == 0)
+    }
+
+    /// The benchmark's subcommand, with one optional positional argument per parameter.
+    fn command<'help>(&self) -> Command<'help> {
+        self.params.iter().enumerate().fold(
+            Command::new(self.cli_name()).about(self.about),
+            |cmd, (i, &param)| {
+                let arg = Arg::new(param)
+                    .index(i + 1)
+                    .default_value(self.default_args[i]);
+                cmd.arg(if self.is_int_param(i) {
+                    arg.validator(|s| s.parse::<u64>())
+                } else {
+                    arg
+                })
+            },
+        )
+    }
+}
+
+/// A benchmark along with the arguments of its gRPC request, as parsed from the command line.
+///
+/// It implements [`Subcommand`], providing one subcommand per [`Benchmark`] in [`BENCHMARKS`].
+#[derive(Debug, Clone)]
+pub struct BenchCmd {
+    bench: &'static Benchmark,
+    args: RpcArgs,
+}
+
+impl BenchCmd {
+    /// The benchmark to be issued.
+    pub fn bench(&self) -> &'static Benchmark {
+        self.bench
+    }
+
+    /// The arguments of the benchmark's gRPC request.
+    pub fn rpc_args(&self) -> &RpcArgs {
+        &self.args
+    }
+}
+
+impl FromArgMatches for BenchCmd {
+    fn from_arg_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        let (name, sub) = matches.subcommand().ok_or_else(|| {
+            clap::Error::raw(ErrorKind::MissingSubcommand, "a benchmark is required")
+        })?;
+        let bench = Benchmark::lookup(name)
+            .map_err(|err| clap::Error::raw(ErrorKind::UnrecognizedSubcommand, err))?;
+        let args = bench
+            .params
+            .iter()
+            .map(|&param| sub.value_of(param).unwrap_or_default())
+            .collect::<Vec<_>>();
+        let args = bench
+            .rpc_args(&args)
+            .map_err(|err| clap::Error::raw(ErrorKind::ValueValidation, err))?;
+        Ok(Self { bench, args })
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> Result<(), clap::Error> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+impl Subcommand for BenchCmd {
+    fn augment_subcommands(cmd: Command<'_>) -> Command<'_> {
+        BENCHMARKS
+            .iter()
+            .fold(cmd, |cmd, bench| cmd.subcommand(bench.command()))
+    }
+
+    fn augment_subcommands_for_update(cmd: Command<'_>) -> Command<'_> {
+        Self::augment_subcommands(cmd)
+    }
+
+    fn has_subcommand(name: &str) -> bool {
+        Benchmark::lookup(name).is_ok()
+    }
+}
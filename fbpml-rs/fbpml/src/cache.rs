@@ -0,0 +1,216 @@
+//! Evicting files from the page cache and verifying that they are no longer resident, so that
+//! MicroVMs are restored from truly cold snapshots.
+//!
+//! Eviction is requested through `posix_fadvise(POSIX_FADV_DONTNEED)` on each file (after flushing
+//! its dirty pages, which cannot be dropped otherwise) and then verified through `mincore(2)` on a
+//! fresh mapping of it. Files whose pages cannot be evicted (e.g., on tmpfs, where the page cache is
+//! the only copy of the data) are reported as such, rather than being silently left warm.
+//!
+//! The same `mincore(2)` probe is also used to report how much of a MicroVM's snapshot (and rootfs)
+//! files resided in the page cache right before it was restored and after it was benchmarked (see
+//! [`SnapshotFiles::probe`]), so that cold-start latencies can be correlated with working sets.
+//!
+//! Note that files on DAX-mounted filesystems bypass the page cache altogether, hence they are
+//! always reported as non-resident.
+
+use std::{
+    fmt,
+    fs::File,
+    io,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Errors that may occur while evicting files from the page cache.
+#[derive(Debug, thiserror::Error)]
+pub enum CacheError {
+    /// A system call on the file failed.
+    #[error("failed to {op} '{}': {source}", .path.display())]
+    Io {
+        op: &'static str,
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// The file was advised out of the page cache, but some of its pages are still resident.
+    #[error("'{}' could not be evicted from the page cache ({residency}); is it on tmpfs?", .path.display())]
+    StillResident { path: PathBuf, residency: Residency },
+}
+
+/// How much of a file currently resides in the page cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Residency {
+    /// The number of the file's pages that are resident.
+    pub resident: usize,
+    /// The total number of pages the file spans.
+    pub total: usize,
+}
+
+impl Residency {
+    /// Whether none of the file's pages are resident.
+    pub fn is_cold(&self) -> bool {
+        self.resident == 0
+    }
+}
+
+impl fmt::Display for Residency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{} pages resident", self.resident, self.total)
+    }
+}
+
+/// The files that back a MicroVM restored from a snapshot.
+#[derive(Debug, Clone)]
+pub struct SnapshotFiles {
+    /// The state file of the snapshot.
+    pub state: PathBuf,
+    /// The memory file of the snapshot.
+    pub memory: PathBuf,
+    /// The rootfs image of the MicroVM, if it is to be probed as well.
+    pub rootfs: Option<PathBuf>,
+}
+
+impl SnapshotFiles {
+    /// Report how much of each one of the files currently resides in the page cache.
+    pub fn probe(&self) -> Result<SnapshotResidency, CacheError> {
+        Ok(SnapshotResidency {
+            state: residency(&self.state)?,
+            memory: residency(&self.memory)?,
+            rootfs: self.rootfs.as_deref().map(residency).transpose()?,
+        })
+    }
+}
+
+/// How much of each one of a MicroVM's [`SnapshotFiles`] resided in the page cache at some point.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotResidency {
+    pub state: Residency,
+    pub memory: Residency,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rootfs: Option<Residency>,
+}
+
+/// How much of a MicroVM's [`SnapshotFiles`] resided in the page cache right before it was
+/// restored, and after the warm request had been served.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResidencyReport {
+    pub pre_restore: SnapshotResidency,
+    pub post_warm: SnapshotResidency,
+}
+
+impl ResidencyReport {
+    /// The resident page counts of the report in CSV order (see
+    /// [`RESIDENCY_COLUMNS`](crate::output::RESIDENCY_COLUMNS)); the rootfs image's are missing if
+    /// it was not probed.
+    pub fn resident_pages(&self) -> [Option<usize>; 6] {
+        let Self {
+            pre_restore: pre,
+            post_warm: post,
+        } = self;
+        [
+            Some(pre.state.resident),
+            Some(pre.memory.resident),
+            pre.rootfs.map(|r| r.resident),
+            Some(post.state.resident),
+            Some(post.memory.resident),
+            post.rootfs.map(|r| r.resident),
+        ]
+    }
+}
+
+/// The size of a page, in bytes.
+pub fn page_size() -> usize {
+    // SAFETY: sysconf(3) has no preconditions; `_SC_PAGESIZE` is always supported.
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// Report how many of the pages of the file at `path` are currently resident in the page cache.
+pub fn residency(path: &Path) -> Result<Residency, CacheError> {
+    let file = open(path)?;
+    residency_of(&file).map_err(io_error("mincore", path))
+}
+
+/// Evict the file at `path` from the page cache, reporting how many of its pages stayed resident.
+///
+/// Failing to evict some (or all) of its pages is not considered an error here; see [`evict_all`].
+pub fn evict(path: &Path) -> Result<Residency, CacheError> {
+    let file = open(path)?;
+    file.sync_data().map_err(io_error("flush", path))?;
+
+    // SAFETY: The file descriptor remains open for the duration of the call.
+    let ret = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    if ret != 0 {
+        return Err(io_error("evict", path)(io::Error::from_raw_os_error(ret)));
+    }
+
+    residency_of(&file).map_err(io_error("mincore", path))
+}
+
+/// Evict all given files from the page cache, failing if any of their pages stayed resident.
+pub fn evict_all(paths: &[impl AsRef<Path>]) -> Result<(), CacheError> {
+    for path in paths {
+        let path = path.as_ref();
+        let residency = evict(path)?;
+        if !residency.is_cold() {
+            return Err(CacheError::StillResident {
+                path: path.to_path_buf(),
+                residency,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn open(path: &Path) -> Result<File, CacheError> {
+    File::open(path).map_err(io_error("open", path))
+}
+
+fn io_error<'a>(op: &'static str, path: &'a Path) -> impl FnOnce(io::Error) -> CacheError + 'a {
+    move |source| CacheError::Io {
+        op,
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// Map `file` (without faulting any of its pages in) and query the residency of its pages through
+/// `mincore(2)`.
+fn residency_of(file: &File) -> io::Result<Residency> {
+    let len = file.metadata()?.len() as usize;
+    if len == 0 {
+        return Ok(Residency::default());
+    }
+    let total = len.div_ceil(page_size());
+    let mut vec = vec![0u8; total];
+
+    // SAFETY: A fresh shared mapping of the whole file is requested; it is never dereferenced.
+    let addr = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        )
+    };
+    if addr == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `addr` is page-aligned and mapped for `len` bytes, and `vec` holds one byte for each
+    // page in that range.
+    let ret = unsafe { libc::mincore(addr, len, vec.as_mut_ptr()) };
+    let err = io::Error::last_os_error();
+    // SAFETY: `addr` was mapped above for `len` bytes and is not used afterwards.
+    unsafe { libc::munmap(addr, len) };
+    if ret != 0 {
+        return Err(err);
+    }
+
+    let resident = vec.iter().filter(|&&page| page & 1 != 0).count();
+    Ok(Residency { resident, total })
+}
@@ -0,0 +1,269 @@
+//! A gRPC client for the benchmarks, able to issue all requests over a single connection.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tonic::{
+    body::BoxBody,
+    codegen::{http, BoxFuture, Context, Poll, Service},
+    transport::{Channel, Endpoint},
+};
+
+use fbpml_rpc::{
+    clock_client::ClockClient, one_argument_client::OneArgumentClient,
+    string_argument_client::StringArgumentClient, two_arguments_client::TwoArgumentsClient,
+    zero_arguments_client::ZeroArgumentsClient, OneArgumentRequest, ServiceResponse,
+    SetTimeRequest, StringArgumentRequest, TwoArgumentsRequest,
+};
+
+use crate::{Error, Result};
+
+/// The arguments of a benchmark's request, which also determine the gRPC service to be used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcArgs {
+    /// A request to the `ZeroArguments` service.
+    Zero,
+    /// A request to the `OneArgument` service.
+    One(u64),
+    /// A request to the `TwoArguments` service.
+    Two(u64, u64),
+    /// A request to the `StringArgument` service.
+    Str(String, u64),
+}
+
+impl RpcArgs {
+    /// A human-readable name of the kind of the request.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Zero => "zero-arguments",
+            Self::One(_) => "one-argument",
+            Self::Two(_, _) => "two-arguments",
+            Self::Str(_, _) => "string-argument",
+        }
+    }
+}
+
+/// A client for the gRPC server inside a MicroVM.
+///
+/// Unless [`BenchClient::connect`] is called, every request establishes a new connection to the
+/// server, excluding the connection setup from the duration measured for the request. Once
+/// connected, all subsequent requests are issued over the same `Channel`, so that "warm" requests
+/// do not pay for a TCP and HTTP/2 handshake.
+#[derive(Debug, Clone)]
+pub struct BenchClient {
+    endpoint: Endpoint,
+    channel: Option<Channel>,
+}
+
+impl BenchClient {
+    /// Create a new client for the server at `server_addr` (e.g., `http://10.0.0.2:50051`),
+    /// without connecting to it yet.
+    pub fn new(server_addr: String) -> Result<Self> {
+        Ok(Self {
+            endpoint: Endpoint::from_shared(server_addr)?,
+            channel: None,
+        })
+    }
+
+    /// Fail any request that is not answered within `timeout` with an [`Error::Rpc`] of code
+    /// `DeadlineExceeded` (and bound any connection attempt alike), instead of waiting on an
+    /// unresponsive server indefinitely; `None` means no timeout, which is the default.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        if let Some(timeout) = timeout {
+            self.endpoint = self.endpoint.timeout(timeout).connect_timeout(timeout);
+        }
+        self
+    }
+
+    /// Establish the connection to be used for all subsequent requests, returning the time it
+    /// took (i.e., TCP and HTTP/2 handshakes).
+    pub async fn connect(&mut self) -> Result<Duration> {
+        let start = Instant::now();
+        let channel = self.new_channel().await?;
+        let elapsed = Instant::now() - start;
+
+        self.channel = Some(channel);
+        Ok(elapsed)
+    }
+
+    /// Whether [`BenchClient::connect`] has been called successfully.
+    pub fn is_connected(&self) -> bool {
+        self.channel.is_some()
+    }
+
+    /// Issue a request with the given arguments, returning the time it took as measured by the
+    /// client, along with the response of the server.
+    pub async fn bench(&self, args: &RpcArgs) -> Result<(Duration, ServiceResponse)> {
+        let (timing, resp) = self.bench_timed(args).await?;
+        Ok((timing.total, resp))
+    }
+
+    /// Issue a request with the given arguments, like [`BenchClient::bench`], but also report the
+    /// time until the first byte of the response (i.e., its HTTP/2 headers) arrived.
+    pub async fn bench_timed(&self, args: &RpcArgs) -> Result<(RpcTiming, ServiceResponse)> {
+        let channel = match &self.channel {
+            Some(channel) => channel.clone(),
+            None => self.new_channel().await?,
+        };
+        let channel = HeadTimer::new(channel);
+        let head = channel.head.clone();
+
+        // Issue the request & time it
+        let (client_start, resp) = match args {
+            RpcArgs::Zero => {
+                let mut client = ZeroArgumentsClient::new(channel);
+                let req = tonic::Request::new(());
+
+                let client_start = Instant::now();
+                (client_start, client.bench(req).await?)
+            }
+            &RpcArgs::One(arg) => {
+                let mut client = OneArgumentClient::new(channel);
+                let req = tonic::Request::new(OneArgumentRequest { arg });
+
+                let client_start = Instant::now();
+                (client_start, client.bench(req).await?)
+            }
+            &RpcArgs::Two(arg1, arg2) => {
+                let mut client = TwoArgumentsClient::new(channel);
+                let req = tonic::Request::new(TwoArgumentsRequest { arg1, arg2 });
+
+                let client_start = Instant::now();
+                (client_start, client.bench(req).await?)
+            }
+            RpcArgs::Str(arg, len) => {
+                let mut client = StringArgumentClient::new(channel);
+                let req = tonic::Request::new(StringArgumentRequest {
+                    arg: arg.clone(),
+                    len: *len,
+                });
+
+                let client_start = Instant::now();
+                (client_start, client.bench(req).await?)
+            }
+        };
+        let client_end = Instant::now();
+
+        let head = head.lock().unwrap().unwrap_or(client_end);
+        let timing = RpcTiming {
+            first_byte: head - client_start,
+            total: client_end - client_start,
+        };
+        Ok((timing, validate(resp.into_inner())?))
+    }
+
+    /// Set the guest's wall clock to the host's through the `Clock` service, returning how long
+    /// that took as measured by the client, along with how far behind the guest's clock was.
+    ///
+    /// A restored guest's clock is as of when its snapshot was created, hence this is meant to be
+    /// called right after resuming it, before any request that depends on the time (e.g., one
+    /// that fetches its input from MinIO). Unless [`BenchClient::connect`] has been called first,
+    /// the request is issued over a new connection, which is then dropped.
+    pub async fn sync_clock(&self) -> Result<ClockSync> {
+        let channel = match &self.channel {
+            Some(channel) => channel.clone(),
+            None => self.new_channel().await?,
+        };
+        let mut client = ClockClient::new(channel);
+
+        let start = Instant::now();
+        let now = SystemTime::now();
+        let req = tonic::Request::new(SetTimeRequest {
+            time: Some(now.into()),
+        });
+        let resp = client.set_time(req).await?.into_inner();
+        let duration = Instant::now() - start;
+
+        let previous = resp
+            .previous
+            .and_then(|previous| SystemTime::try_from(previous).ok())
+            .ok_or(Error::MalformedResponse)?;
+        Ok(ClockSync {
+            duration,
+            skew: now.duration_since(previous).unwrap_or_default(),
+        })
+    }
+
+    /// Drop the connection established by [`BenchClient::connect`], if any, so that subsequent
+    /// requests establish a new connection each.
+    pub fn disconnect(&mut self) {
+        self.channel = None;
+    }
+
+    async fn new_channel(&self) -> Result<Channel> {
+        self.endpoint
+            .connect()
+            .await
+            .map_err(|source| Error::Connect {
+                addr: self.endpoint.uri().to_string(),
+                source,
+            })
+    }
+}
+
+/// The client-side timing of a single request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RpcTiming {
+    /// The time until the headers of the response arrived (i.e., its first byte).
+    pub first_byte: Duration,
+    /// The time until the whole response (i.e., including its trailers) arrived.
+    pub total: Duration,
+}
+
+/// The outcome of setting a guest's wall clock (see [`BenchClient::sync_clock`]).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockSync {
+    /// The time it took to set the clock, as measured by the client.
+    pub duration: Duration,
+    /// How far behind the host's the guest's clock was before it was set; zero if it was ahead.
+    pub skew: Duration,
+}
+
+/// Wraps a [`Channel`] to record the moment the head of the (single) response arrives, which
+/// precedes the moment the generated client yields the decoded message.
+#[derive(Clone)]
+struct HeadTimer {
+    inner: Channel,
+    head: Arc<Mutex<Option<Instant>>>,
+}
+
+impl HeadTimer {
+    fn new(inner: Channel) -> Self {
+        Self {
+            inner,
+            head: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+impl Service<http::Request<BoxBody>> for HeadTimer {
+    type Response = <Channel as Service<http::Request<BoxBody>>>::Response;
+    type Error = <Channel as Service<http::Request<BoxBody>>>::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<BoxBody>) -> Self::Future {
+        let fut = self.inner.call(req);
+        let head = self.head.clone();
+        Box::pin(async move {
+            let resp = fut.await;
+            *head.lock().unwrap() = Some(Instant::now());
+            resp
+        })
+    }
+}
+
+/// Make sure the given `ServiceResponse` is well-formed.
+fn validate(resp: ServiceResponse) -> Result<ServiceResponse> {
+    match resp.response_duration.clone().map(Duration::try_from) {
+        Some(Ok(_)) => Ok(resp),
+        _ => Err(Error::MalformedResponse),
+    }
+}
@@ -0,0 +1,221 @@
+//! Working with the memory files of Firecracker's diff snapshots.
+//!
+//! A diff snapshot's memory file only contains the guest pages that were dirtied since the previous
+//! snapshot of the MicroVM was taken; it spans the whole guest memory, but all other pages are
+//! holes. Firecracker cannot load such a file on its own: it has to be layered on top of the memory
+//! file of the snapshot it was based on (see [`rebase`], which does what Firecracker's
+//! `rebase-snap` tool does), and the result is then loaded along with the diff snapshot's state
+//! file.
+//!
+//! `build-snapshots --diff-snapshots N` stores, next to the full snapshot of each MicroVM (i.e.,
+//! `$STORE/{snapshot,memory}-$IDh.file`), the `K`-th diff snapshot (for each `K` in `1..=N`) under
+//! `$STORE/diffK/`, as:
+//!
+//! - `snapshot-$IDh.file`: the state file of the diff snapshot;
+//! - `memory-$IDh.layer`: the sparse memory file of the diff snapshot, as written by Firecracker;
+//! - `memory-$IDh.file`: the full snapshot's memory file, with all layers up to the `K`-th one
+//!   applied on top of it, so that `diffK/` can be restored from just like any full snapshot.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    os::unix::{
+        fs::{FileExt, MetadataExt},
+        io::AsRawFd,
+    },
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The size of the chunks in which layers are copied onto their base.
+const CHUNK: usize = 1 << 21;
+
+/// Errors that may occur while working with the memory files of diff snapshots.
+#[derive(Debug, thiserror::Error)]
+pub enum DiffError {
+    /// A system call on one of the files failed.
+    #[error("failed to {op} '{}': {source}", .path.display())]
+    Io {
+        op: &'static str,
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// The layer spans more than the memory file it is to be applied on.
+    #[error("'{}' ({layer_len} bytes) does not fit on '{}' ({base_len} bytes)", .layer.display(), .base.display())]
+    SizeMismatch {
+        layer: PathBuf,
+        layer_len: u64,
+        base: PathBuf,
+        base_len: u64,
+    },
+}
+
+/// The name of the sparse memory file of MicroVM `id`'s diff snapshot.
+pub fn layer_name(id: usize) -> String {
+    format!("memory-{id:02X}.layer")
+}
+
+/// The size of a (possibly sparse) file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileSize {
+    /// The apparent size of the file, in bytes.
+    pub len: u64,
+    /// The bytes actually allocated for the file on its filesystem; less than `len` for sparse
+    /// files.
+    pub allocated: u64,
+}
+
+impl FileSize {
+    /// The size of the file at `path`.
+    pub fn of(path: &Path) -> Result<Self, DiffError> {
+        let meta = fs::metadata(path).map_err(io_error("stat", path))?;
+        Ok(Self {
+            len: meta.len(),
+            // `st_blocks` is always in units of 512 bytes, regardless of the filesystem
+            allocated: meta.blocks() * 512,
+        })
+    }
+}
+
+/// Apply the diff snapshot memory file at `layer` on top of the memory file at `base`, in place;
+/// returns the number of bytes copied (i.e., the size of the data in `layer`).
+///
+/// Only the data regions of `layer` are copied (as reported by `lseek(2)` with `SEEK_DATA` and
+/// `SEEK_HOLE`), hence its holes retain the content of `base`.
+pub fn rebase(base: &Path, layer: &Path) -> Result<u64, DiffError> {
+    let src = File::open(layer).map_err(io_error("open", layer))?;
+    let dst = OpenOptions::new()
+        .write(true)
+        .open(base)
+        .map_err(io_error("open", base))?;
+    let layer_len = src.metadata().map_err(io_error("stat", layer))?.len();
+    let base_len = dst.metadata().map_err(io_error("stat", base))?.len();
+    if layer_len > base_len {
+        return Err(DiffError::SizeMismatch {
+            layer: layer.to_path_buf(),
+            layer_len,
+            base: base.to_path_buf(),
+            base_len,
+        });
+    }
+
+    let mut buf = vec![0; CHUNK];
+    let mut copied = 0;
+    let mut offset = 0;
+    while let Some(start) = seek(&src, offset, libc::SEEK_DATA).map_err(io_error("seek", layer))? {
+        let end = seek(&src, start, libc::SEEK_HOLE)
+            .map_err(io_error("seek", layer))?
+            .unwrap_or(layer_len);
+        let mut pos = start;
+        while pos < end {
+            let len = CHUNK.min((end - pos) as usize);
+            src.read_exact_at(&mut buf[..len], pos)
+                .map_err(io_error("read", layer))?;
+            dst.write_all_at(&buf[..len], pos)
+                .map_err(io_error("write", base))?;
+            pos += len as u64;
+        }
+        copied += end - start;
+        offset = end;
+    }
+    dst.sync_data().map_err(io_error("flush", base))?;
+    Ok(copied)
+}
+
+/// `lseek(2)` the given `file` to the next data region or hole (i.e., `whence` is either
+/// `SEEK_DATA` or `SEEK_HOLE`) at or after `offset`; `None` stands for no more data.
+fn seek(file: &File, offset: u64, whence: i32) -> io::Result<Option<u64>> {
+    // SAFETY: The file descriptor remains open for the duration of the call.
+    let ret = unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) };
+    if ret >= 0 {
+        return Ok(Some(ret as u64));
+    }
+    match io::Error::last_os_error() {
+        err if err.raw_os_error() == Some(libc::ENXIO) => Ok(None),
+        err => Err(err),
+    }
+}
+
+fn io_error<'a>(op: &'static str, path: &'a Path) -> impl FnOnce(io::Error) -> DiffError + 'a {
+    move |source| DiffError::Io {
+        op,
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    const PAGE: usize = 1 << 16;
+
+    /// A fresh path in the temporary directory, unique to this process and `name`.
+    fn tmp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("fbpml-diff-{}-{name}", process::id()))
+    }
+
+    #[test]
+    fn rebase_copies_data_and_keeps_holes() {
+        let (base, layer) = (tmp_path("rebase.file"), tmp_path("rebase.layer"));
+        fs::write(&base, vec![0xbb; 4 * PAGE]).unwrap();
+        // A sparse layer that spans the whole base, with data in its 2nd and (half of its) 4th page
+        let file = File::create(&layer).unwrap();
+        file.set_len(4 * PAGE as u64).unwrap();
+        file.write_all_at(&[0xaa; PAGE], PAGE as u64).unwrap();
+        file.write_all_at(&[0xcc; PAGE / 2], 3 * PAGE as u64)
+            .unwrap();
+        drop(file);
+
+        let copied = rebase(&base, &layer).unwrap();
+        let content = fs::read(&base).unwrap();
+        fs::remove_file(&base).unwrap();
+        fs::remove_file(&layer).unwrap();
+
+        assert_eq!(copied, (PAGE + PAGE / 2) as u64);
+        assert_eq!(content.len(), 4 * PAGE);
+        assert!(content[..PAGE].iter().all(|&b| b == 0xbb));
+        assert!(content[PAGE..2 * PAGE].iter().all(|&b| b == 0xaa));
+        assert!(content[2 * PAGE..3 * PAGE].iter().all(|&b| b == 0xbb));
+        assert!(content[3 * PAGE..3 * PAGE + PAGE / 2]
+            .iter()
+            .all(|&b| b == 0xcc));
+        assert!(content[3 * PAGE + PAGE / 2..].iter().all(|&b| b == 0xbb));
+    }
+
+    #[test]
+    fn rebase_rejects_larger_layers() {
+        let (base, layer) = (tmp_path("larger.file"), tmp_path("larger.layer"));
+        fs::write(&base, vec![0xbb; PAGE]).unwrap();
+        fs::write(&layer, vec![0xaa; 2 * PAGE]).unwrap();
+
+        let res = rebase(&base, &layer);
+        let content = fs::read(&base).unwrap();
+        fs::remove_file(&base).unwrap();
+        fs::remove_file(&layer).unwrap();
+
+        assert!(matches!(res, Err(DiffError::SizeMismatch { .. })));
+        assert!(content.iter().all(|&b| b == 0xbb));
+    }
+
+    #[test]
+    fn file_size_of_sparse_file() {
+        let path = tmp_path("sparse.layer");
+        let file = File::create(&path).unwrap();
+        file.set_len(4 * PAGE as u64).unwrap();
+        file.write_all_at(&[0xaa; PAGE], 0).unwrap();
+        file.sync_all().unwrap();
+        drop(file);
+
+        let size = FileSize::of(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(size.len, 4 * PAGE as u64);
+        assert!(size.allocated >= PAGE as u64 && size.allocated < size.len);
+    }
+}
@@ -0,0 +1,101 @@
+use std::error::Error as _;
+
+use hyper::http::uri::InvalidUri;
+use tonic::{transport::TimeoutExpired, Code};
+
+use crate::{cache::CacheError, firecracker::ApiError, vmm::VmmError};
+
+/// The errors that may occur while restoring, resuming and talking to a MicroVM.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The address of the gRPC server is not a valid URI.
+    #[error("invalid server address: {0}")]
+    InvalidAddress(#[from] InvalidUri),
+
+    /// Failed to establish a connection to the gRPC server inside the MicroVM.
+    #[error("failed to connect to '{addr}': {source}")]
+    Connect {
+        addr: String,
+        #[source]
+        source: tonic::transport::Error,
+    },
+
+    /// The gRPC request was issued, but it was answered with a non-OK status (which is also the
+    /// case for deadlines that were exceeded, including the client's own; see
+    /// [`BenchClient::timeout`](crate::BenchClient::timeout)).
+    #[error("gRPC request failed with status '{code:?}': {}", .status.message())]
+    Rpc {
+        code: Code,
+        #[source]
+        status: Box<tonic::Status>,
+    },
+
+    /// Firecracker rejected the request to load the snapshot.
+    #[error("failed to load the snapshot: {0}")]
+    SnapshotLoad(#[source] ApiError),
+
+    /// Firecracker rejected the request to resume the MicroVM.
+    #[error("failed to resume the MicroVM: {0}")]
+    Resume(#[source] ApiError),
+
+    /// The server's `ServiceResponse` lacks the `response_duration` field, or it is negative.
+    #[error("malformed ServiceResponse: missing or negative 'response_duration'")]
+    MalformedResponse,
+
+    /// Failed to probe the page cache residency of the MicroVM's files.
+    #[error(transparent)]
+    Cache(#[from] CacheError),
+
+    /// Failed to spawn (or to terminate) the Firecracker process of the MicroVM.
+    #[error(transparent)]
+    Vmm(#[from] VmmError),
+}
+
+impl Error {
+    /// A short, stable description of the class of the error, suitable for machine-readable
+    /// reports.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::InvalidAddress(_) => "invalid-address",
+            Self::Connect { .. } => "connect",
+            Self::Rpc { code, .. } if *code == Code::DeadlineExceeded => "timeout",
+            Self::Rpc { .. } => "rpc",
+            Self::SnapshotLoad(_) => "snapshot-load",
+            Self::Resume(_) => "resume",
+            Self::MalformedResponse => "malformed-response",
+            Self::Cache(_) => "cache",
+            Self::Vmm(_) => "vmm",
+        }
+    }
+
+    /// The gRPC status code, if this is an [`Error::Rpc`].
+    pub fn code(&self) -> Option<Code> {
+        match self {
+            Self::Rpc { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        // tonic reports requests that exceeded the channel's own timeout as cancelled, rather than
+        // as having exceeded their deadline
+        let mut source = status.source();
+        while let Some(err) = source {
+            if err.is::<TimeoutExpired>() {
+                return Self::Rpc {
+                    code: Code::DeadlineExceeded,
+                    status: Box::new(status),
+                };
+            }
+            source = err.source();
+        }
+        Self::Rpc {
+            code: status.code(),
+            status: Box::new(status),
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -0,0 +1,187 @@
+//! Parsing the metrics that Firecracker writes to the file (or named pipe) configured through
+//! `PUT /metrics`.
+//!
+//! Firecracker appends one JSON object per line to its metrics file, every time its metrics are
+//! flushed: periodically (every 60 seconds) and upon `PUT /actions` with `FlushMetrics` (see
+//! [`FirecrackerApi::flush_metrics`]). Counters (e.g., the bytes read by the block devices) are
+//! reset after each flush, hence each line only reports what happened since the previous one;
+//! latencies (e.g., of `load_snapshot`) retain their last value across flushes.
+//!
+//! Only the groups of metrics that are of interest for snapshotting are parsed (i.e., `vmm`,
+//! `api_server`, `block`, `net` and `latencies_us`); all others are ignored, as are any fields
+//! that may be missing in the given version of Firecracker.
+//!
+//! [`FirecrackerApi::flush_metrics`]: crate::FirecrackerApi::flush_metrics
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Errors that may occur while reading Firecracker's metrics.
+#[derive(Debug, thiserror::Error)]
+pub enum FcMetricsError {
+    /// The metrics file could not be read.
+    #[error("failed to read Firecracker metrics '{}': {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// A line of the metrics file is not a valid metrics object.
+    #[error("{}:{line}: malformed Firecracker metrics: {source}", .path.display())]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+/// A single flush of Firecracker's metrics (i.e., a line of its metrics file).
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FcMetrics {
+    /// When the metrics were flushed, in milliseconds since the Unix epoch.
+    pub utc_timestamp_ms: u64,
+    pub api_server: ApiServerMetrics,
+    pub block: BlockMetrics,
+    pub net: NetMetrics,
+    pub latencies_us: LatenciesUs,
+    pub vmm: VmmMetrics,
+}
+
+/// Metrics of the API server of Firecracker.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiServerMetrics {
+    /// The time from the process' start until the API server was ready, in microseconds.
+    pub process_startup_time_us: u64,
+    /// The CPU time from the process' start until the API server was ready, in microseconds.
+    pub process_startup_time_cpu_us: u64,
+    pub sync_response_fails: u64,
+    pub sync_vmm_send_timeout_count: u64,
+}
+
+/// Metrics of all block devices of the MicroVM, together.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlockMetrics {
+    pub activate_fails: u64,
+    pub cfg_fails: u64,
+    pub event_fails: u64,
+    pub execute_fails: u64,
+    pub invalid_reqs_count: u64,
+    pub flush_count: u64,
+    pub queue_event_count: u64,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub read_count: u64,
+    pub write_count: u64,
+}
+
+/// Metrics of all network interfaces of the MicroVM, together.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetMetrics {
+    pub activate_fails: u64,
+    pub cfg_fails: u64,
+    pub event_fails: u64,
+    pub rx_bytes_count: u64,
+    pub rx_packets_count: u64,
+    pub rx_fails: u64,
+    pub tx_bytes_count: u64,
+    pub tx_packets_count: u64,
+    pub tx_fails: u64,
+}
+
+/// The latencies of snapshot-related operations, in microseconds, as measured by Firecracker: the
+/// plain ones span the whole handling of the request, while the `vmm_` ones only the part of it
+/// that takes place in the VMM thread.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LatenciesUs {
+    pub full_create_snapshot: u64,
+    pub diff_create_snapshot: u64,
+    pub load_snapshot: u64,
+    pub pause_vm: u64,
+    pub resume_vm: u64,
+    pub vmm_full_create_snapshot: u64,
+    pub vmm_diff_create_snapshot: u64,
+    pub vmm_load_snapshot: u64,
+    pub vmm_pause_vm: u64,
+    pub vmm_resume_vm: u64,
+}
+
+/// Metrics of the VMM itself.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VmmMetrics {
+    pub device_events: u64,
+    pub panic_count: u64,
+}
+
+impl FcMetrics {
+    /// Parse all metrics flushed to the file at `path`, in the order they were flushed; blank
+    /// lines are ignored.
+    pub fn from_file(path: &Path) -> Result<Vec<Self>, FcMetricsError> {
+        let content = fs::read_to_string(path).map_err(|source| FcMetricsError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|source| FcMetricsError::Parse {
+                    path: path.to_path_buf(),
+                    line: i + 1,
+                    source,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Firecracker's own view of how long it took to restore a MicroVM from its snapshot and to
+/// resume it, to be compared with the durations measured by the client.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestoreLatencies {
+    pub load_snapshot: Duration,
+    pub vmm_load_snapshot: Duration,
+    pub resume_vm: Duration,
+    pub vmm_resume_vm: Duration,
+}
+
+impl RestoreLatencies {
+    /// The latencies reported by the last of the given `metrics` that accounts for a snapshot
+    /// having been loaded, if any.
+    pub fn from_metrics(metrics: &[FcMetrics]) -> Option<Self> {
+        let l = metrics
+            .iter()
+            .rev()
+            .map(|m| &m.latencies_us)
+            .find(|l| l.load_snapshot > 0)?;
+        Some(Self {
+            load_snapshot: Duration::from_micros(l.load_snapshot),
+            vmm_load_snapshot: Duration::from_micros(l.vmm_load_snapshot),
+            resume_vm: Duration::from_micros(l.resume_vm),
+            vmm_resume_vm: Duration::from_micros(l.vmm_resume_vm),
+        })
+    }
+
+    /// All latencies, in the order of [`output::FC_LATENCY_COLUMNS`](crate::output::FC_LATENCY_COLUMNS).
+    pub fn durations(&self) -> [Duration; 4] {
+        [
+            self.load_snapshot,
+            self.vmm_load_snapshot,
+            self.resume_vm,
+            self.vmm_resume_vm,
+        ]
+    }
+}
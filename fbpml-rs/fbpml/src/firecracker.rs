@@ -0,0 +1,432 @@
+//! A typed asynchronous client for the subset of Firecracker's HTTP API (served over its Unix
+//! domain socket) that is used throughout fbpml.
+//!
+//! Request bodies are plain `serde`-serializable structs, named after the corresponding objects of
+//! Firecracker's OpenAPI specification. The same structs (see [`VmConfig`]) can also be used to
+//! render the JSON configuration file that is passed to Firecracker via `--config-file`.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use hyper::{body, Body, Client, Method, Request, StatusCode};
+use hyperlocal::{UnixClientExt, UnixConnector, Uri};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+const ACCEPT: &str = "Accept";
+const CONTENT_TYPE: &str = "Content-Type";
+const APPLICATION_JSON: &str = "application/json";
+
+/// Errors that may occur while talking to Firecracker's API server.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    /// The request body could not be serialized, or the response body could not be deserialized.
+    #[error("{method} {endpoint}: malformed JSON body: {source}")]
+    Json {
+        method: Method,
+        endpoint: &'static str,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    /// The HTTP request could not be constructed.
+    #[error("{method} {endpoint}: failed to construct HTTP request: {source}")]
+    Request {
+        method: Method,
+        endpoint: &'static str,
+        #[source]
+        source: hyper::http::Error,
+    },
+
+    /// The HTTP request could not be sent, or the response could not be received.
+    #[error("{method} {endpoint} @ '{}': {source}", .sock.display())]
+    Transport {
+        method: Method,
+        endpoint: &'static str,
+        sock: PathBuf,
+        #[source]
+        source: hyper::Error,
+    },
+
+    /// Firecracker received the request but rejected it; `fault_message` is the body of its
+    /// response (or the raw body, if it could not be parsed as such).
+    #[error("{method} {endpoint}: Firecracker responded with {status}: {fault_message}")]
+    Fault {
+        method: Method,
+        endpoint: &'static str,
+        status: StatusCode,
+        fault_message: String,
+    },
+}
+
+impl ApiError {
+    /// Returns the `fault_message` reported by Firecracker, if any.
+    pub fn fault_message(&self) -> Option<&str> {
+        match self {
+            Self::Fault { fault_message, .. } => Some(fault_message),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T, E = ApiError> = std::result::Result<T, E>;
+
+/// The body of an error response by Firecracker's API server.
+#[derive(Deserialize)]
+struct Fault {
+    fault_message: String,
+}
+
+/// `PUT /boot-source`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BootSource {
+    pub kernel_image_path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boot_args: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initrd_path: Option<PathBuf>,
+}
+
+/// `PUT /drives/{drive_id}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Drive {
+    pub drive_id: String,
+    pub path_on_host: PathBuf,
+    pub is_root_device: bool,
+    pub is_read_only: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partuuid: Option<String>,
+}
+
+/// `PUT /machine-config`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MachineConfig {
+    pub vcpu_count: u64,
+    pub mem_size_mib: u64,
+    #[serde(default)]
+    pub smt: bool,
+    #[serde(default)]
+    pub track_dirty_pages: bool,
+}
+
+/// `PUT /network-interfaces/{iface_id}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkInterface {
+    pub iface_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guest_mac: Option<String>,
+    pub host_dev_name: String,
+}
+
+/// The verbosity of Firecracker's logger.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum LogLevel {
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+/// `PUT /logger`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Logger {
+    pub log_path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<LogLevel>,
+    #[serde(default)]
+    pub show_level: bool,
+    #[serde(default)]
+    pub show_log_origin: bool,
+}
+
+/// `PUT /metrics`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Metrics {
+    pub metrics_path: PathBuf,
+}
+
+/// The type of an action performed through `PUT /actions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ActionType {
+    FlushMetrics,
+    InstanceStart,
+    SendCtrlAltDel,
+}
+
+/// `PUT /actions`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct InstanceActionInfo {
+    pub action_type: ActionType,
+}
+
+/// The state of the MicroVM, as set through `PATCH /vm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VmState {
+    Paused,
+    Resumed,
+}
+
+/// `PATCH /vm`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Vm {
+    pub state: VmState,
+}
+
+/// The type of a snapshot created through `PUT /snapshot/create`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SnapshotType {
+    Full,
+    Diff,
+}
+
+/// `PUT /snapshot/create`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotCreateParams {
+    pub snapshot_type: SnapshotType,
+    pub snapshot_path: PathBuf,
+    pub mem_file_path: PathBuf,
+}
+
+/// `PUT /snapshot/load`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotLoadParams {
+    pub snapshot_path: PathBuf,
+    pub mem_file_path: PathBuf,
+    #[serde(default)]
+    pub enable_diff_snapshots: bool,
+    #[serde(default)]
+    pub resume_vm: bool,
+}
+
+/// The response to `GET /`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceInfo {
+    pub app_name: String,
+    pub id: String,
+    pub state: String,
+    pub vmm_version: String,
+}
+
+/// The whole configuration of a MicroVM, in the format expected by Firecracker's `--config-file`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct VmConfig {
+    pub boot_source: BootSource,
+    pub drives: Vec<Drive>,
+    pub machine_config: MachineConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logger: Option<Logger>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<Metrics>,
+    pub network_interfaces: Vec<NetworkInterface>,
+}
+
+/// A client for the API server of a single Firecracker instance.
+#[derive(Clone)]
+pub struct FirecrackerApi {
+    sock: PathBuf,
+    client: Client<UnixConnector>,
+}
+
+impl fmt::Debug for FirecrackerApi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FirecrackerApi")
+            .field("sock", &self.sock)
+            .finish()
+    }
+}
+
+impl FirecrackerApi {
+    /// Create a new client for the Firecracker instance listening on Unix socket `sock`.
+    pub fn new(sock: impl Into<PathBuf>) -> Self {
+        Self {
+            sock: sock.into(),
+            client: Client::unix(),
+        }
+    }
+
+    /// The path to the Unix domain socket of the Firecracker instance.
+    pub fn socket_path(&self) -> &Path {
+        &self.sock
+    }
+
+    /// `PUT /boot-source`
+    pub async fn put_boot_source(&self, boot_source: &BootSource) -> Result<Duration> {
+        self.send(Method::PUT, "/boot-source", boot_source).await
+    }
+
+    /// `PUT /drives/{drive_id}`
+    pub async fn put_drive(&self, drive: &Drive) -> Result<Duration> {
+        let endpoint = format!("/drives/{}", drive.drive_id);
+        self.send_to(Method::PUT, "/drives", &endpoint, drive).await
+    }
+
+    /// `PUT /machine-config`
+    pub async fn put_machine_config(&self, machine_config: &MachineConfig) -> Result<Duration> {
+        self.send(Method::PUT, "/machine-config", machine_config)
+            .await
+    }
+
+    /// `PUT /network-interfaces/{iface_id}`
+    pub async fn put_network_interface(&self, iface: &NetworkInterface) -> Result<Duration> {
+        let endpoint = format!("/network-interfaces/{}", iface.iface_id);
+        self.send_to(Method::PUT, "/network-interfaces", &endpoint, iface)
+            .await
+    }
+
+    /// `PUT /logger`
+    pub async fn put_logger(&self, logger: &Logger) -> Result<Duration> {
+        self.send(Method::PUT, "/logger", logger).await
+    }
+
+    /// `PUT /metrics`
+    pub async fn put_metrics(&self, metrics: &Metrics) -> Result<Duration> {
+        self.send(Method::PUT, "/metrics", metrics).await
+    }
+
+    /// `PUT /actions`
+    pub async fn put_action(&self, action_type: ActionType) -> Result<Duration> {
+        self.send(Method::PUT, "/actions", &InstanceActionInfo { action_type })
+            .await
+    }
+
+    /// Have Firecracker write its metrics to the configured metrics file right away (i.e.,
+    /// `PUT /actions` with `{"action_type":"FlushMetrics"}`); see [`crate::fcmetrics`].
+    pub async fn flush_metrics(&self) -> Result<Duration> {
+        self.put_action(ActionType::FlushMetrics).await
+    }
+
+    /// `PATCH /vm`
+    pub async fn patch_vm(&self, state: VmState) -> Result<Duration> {
+        self.send(Method::PATCH, "/vm", &Vm { state }).await
+    }
+
+    /// Pause the MicroVM (i.e., `PATCH /vm` with `{"state":"Paused"}`).
+    pub async fn pause(&self) -> Result<Duration> {
+        self.patch_vm(VmState::Paused).await
+    }
+
+    /// Resume the MicroVM (i.e., `PATCH /vm` with `{"state":"Resumed"}`).
+    pub async fn resume(&self) -> Result<Duration> {
+        self.patch_vm(VmState::Resumed).await
+    }
+
+    /// `PUT /snapshot/create`
+    pub async fn create_snapshot(&self, params: &SnapshotCreateParams) -> Result<Duration> {
+        self.send(Method::PUT, "/snapshot/create", params).await
+    }
+
+    /// `PUT /snapshot/load`
+    pub async fn load_snapshot(&self, params: &SnapshotLoadParams) -> Result<Duration> {
+        self.send(Method::PUT, "/snapshot/load", params).await
+    }
+
+    /// `GET /`
+    pub async fn instance_info(&self) -> Result<InstanceInfo> {
+        const ENDPOINT: &str = "/";
+
+        let req = Request::get(Uri::new(&self.sock, ENDPOINT))
+            .header(ACCEPT, APPLICATION_JSON)
+            .body(Body::empty())
+            .map_err(|source| ApiError::Request {
+                method: Method::GET,
+                endpoint: ENDPOINT,
+                source,
+            })?;
+        let (_, status, body) = self.roundtrip(Method::GET, ENDPOINT, req).await?;
+        if status != StatusCode::OK {
+            return Err(fault(Method::GET, ENDPOINT, status, &body));
+        }
+        serde_json::from_slice(&body).map_err(|source| ApiError::Json {
+            method: Method::GET,
+            endpoint: ENDPOINT,
+            source,
+        })
+    }
+
+    /// Serialize `payload` and send it to `endpoint`.
+    async fn send<T: Serialize>(
+        &self,
+        method: Method,
+        endpoint: &'static str,
+        payload: &T,
+    ) -> Result<Duration> {
+        self.send_to(method, endpoint, endpoint, payload).await
+    }
+
+    /// Serialize `payload` and send it to `path`; `endpoint` is the static part of the path, used
+    /// for error reporting.
+    ///
+    /// Returns the time spent waiting for Firecracker to respond, i.e., without the time spent for
+    /// serializing the payload and constructing the request.
+    async fn send_to<T: Serialize>(
+        &self,
+        method: Method,
+        endpoint: &'static str,
+        path: &str,
+        payload: &T,
+    ) -> Result<Duration> {
+        let body = serde_json::to_vec(payload).map_err(|source| ApiError::Json {
+            method: method.clone(),
+            endpoint,
+            source,
+        })?;
+        let req = Request::builder()
+            .method(method.clone())
+            .uri(Uri::new(&self.sock, path))
+            .header(ACCEPT, APPLICATION_JSON)
+            .header(CONTENT_TYPE, APPLICATION_JSON)
+            .body(body.into())
+            .map_err(|source| ApiError::Request {
+                method: method.clone(),
+                endpoint,
+                source,
+            })?;
+
+        let (elapsed, status, body) = self.roundtrip(method.clone(), endpoint, req).await?;
+        match status {
+            StatusCode::NO_CONTENT | StatusCode::OK => Ok(elapsed),
+            status => Err(fault(method, endpoint, status, &body)),
+        }
+    }
+
+    /// Issue `req`, timing it until the response headers have been received, and then collect the
+    /// response's body.
+    async fn roundtrip(
+        &self,
+        method: Method,
+        endpoint: &'static str,
+        req: Request<Body>,
+    ) -> Result<(Duration, StatusCode, body::Bytes)> {
+        let transport = |source| ApiError::Transport {
+            method: method.clone(),
+            endpoint,
+            sock: self.sock.clone(),
+            source,
+        };
+
+        let start = Instant::now();
+        let resp = self.client.request(req).await.map_err(transport)?;
+        let elapsed = Instant::now() - start;
+
+        let status = resp.status();
+        let body = body::to_bytes(resp.into_body()).await.map_err(transport)?;
+        Ok((elapsed, status, body))
+    }
+}
+
+/// Construct an [`ApiError::Fault`] out of an error response.
+fn fault(method: Method, endpoint: &'static str, status: StatusCode, body: &[u8]) -> ApiError {
+    let fault_message = serde_json::from_slice::<Fault>(body)
+        .map(|f| f.fault_message)
+        .unwrap_or_else(|_| String::from_utf8_lossy(body).into_owned());
+    ApiError::Fault {
+        method,
+        endpoint,
+        status,
+        fault_message,
+    }
+}
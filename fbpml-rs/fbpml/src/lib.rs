@@ -1,16 +1,37 @@
+pub mod bench;
+pub mod cache;
+pub mod client;
+pub mod diff;
+mod error;
+pub mod fcmetrics;
+pub mod firecracker;
+pub mod load;
+pub mod manifest;
+pub mod multi;
+pub mod output;
+pub mod replay;
+pub mod timeline;
+pub mod vmm;
+
 use std::fmt;
 use std::time::Duration;
 
-use anyhow::Result;
-use tokio::time::Instant;
+use serde::{Deserialize, Serialize};
+
+use fbpml_rpc::ServiceResponse;
 
-use fbpml_rpc::{
-    one_argument_client::OneArgumentClient, two_arguments_client::TwoArgumentsClient,
-    zero_arguments_client::ZeroArgumentsClient, ServiceResponse, TwoArgumentsRequest,
-};
+use timeline::Timeline;
+
+pub use bench::{BenchCmd, Benchmark};
+pub use client::{BenchClient, ClockSync, RpcArgs, RpcTiming};
+pub use error::{Error, Result};
+pub use firecracker::FirecrackerApi;
+pub use output::TimeUnit;
 
 /// Represents the result of a single run of one of the rpc functions ([`zero_args_rpc`],
-/// [`one_arg_rpc`] and [`two_args_rpc`]); thus includes results for one cold and one warm request.
+/// [`one_arg_rpc`], [`two_args_rpc`] and [`string_arg_rpc`]); thus includes results for one cold
+/// and one warm request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Measurement {
     /// Global duration, measured for the "cold-start" request, also includes the delay for
     /// initializing the gRPC client and the gRPC request (i.e., in addition to the `cold`
@@ -21,10 +42,33 @@ pub struct Measurement {
     restore: Duration,
     /// The delay for resuming the MicroVM after it has been restored from a snapshot.
     resume: Duration,
+    /// The delay for establishing the connection (i.e., TCP and HTTP/2 handshakes) to the gRPC
+    /// server, right before the 'cold-start' request.
+    #[serde(default)]
+    connect: Duration,
+    /// The delay until the first byte of the response to the 'cold-start' request arrived.
+    #[serde(default)]
+    first_byte: Duration,
+    /// When the MicroVM was due to be restored, relative to the epoch shared by all MicroVMs of
+    /// the run.
+    #[serde(default)]
+    arrival: Duration,
+    /// When the restore of the MicroVM actually began (i.e., the global timer started), relative
+    /// to the same epoch; it lags `arrival` while waiting for a restore slot.
+    #[serde(default)]
+    start: Duration,
+    /// The outcome of setting the guest's clock right after resuming it, if it was set; its
+    /// duration is not included in `global`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clock: Option<ClockSync>,
     /// The delays associated with the 'cold-start' request.
     cold: Delays,
     /// The delays associated with the 'warm' request.
     warm: Delays,
+    /// When each phase began and ended, relative to the epoch of the run; it is not included in
+    /// the serialized `Measurement`, but output separately (if at all).
+    #[serde(skip)]
+    timeline: Timeline,
 }
 
 impl From<(Duration, Delays, Delays)> for Measurement {
@@ -33,8 +77,14 @@ impl From<(Duration, Delays, Delays)> for Measurement {
             global,
             restore: Duration::ZERO,
             resume: Duration::ZERO,
+            connect: Duration::ZERO,
+            first_byte: Duration::ZERO,
+            arrival: Duration::ZERO,
+            start: Duration::ZERO,
+            clock: None,
             cold,
             warm,
+            timeline: Timeline::default(),
         }
     }
 }
@@ -45,30 +95,130 @@ impl From<(Duration, Duration, Duration, Delays, Delays)> for Measurement {
             global: g,
             restore: rt,
             resume: rm,
+            connect: Duration::ZERO,
+            first_byte: Duration::ZERO,
+            arrival: Duration::ZERO,
+            start: Duration::ZERO,
+            clock: None,
             cold,
             warm,
+            timeline: Timeline::default(),
         }
     }
 }
 
+impl Measurement {
+    /// The delay for restoring the MicroVM from a snapshot.
+    pub fn restore(&self) -> Duration {
+        self.restore
+    }
+
+    /// The delay for resuming the MicroVM after it has been restored from a snapshot.
+    pub fn resume(&self) -> Duration {
+        self.resume
+    }
+
+    /// The delay for establishing the connection to the gRPC server, right before the
+    /// 'cold-start' request.
+    pub fn connect(&self) -> Duration {
+        self.connect
+    }
+
+    /// The delay until the first byte of the response to the 'cold-start' request arrived.
+    pub fn first_byte(&self) -> Duration {
+        self.first_byte
+    }
+
+    /// Set the delays of the connection establishment and of the first byte of the response to
+    /// the 'cold-start' request.
+    pub fn with_connection(mut self, connect: Duration, first_byte: Duration) -> Self {
+        self.connect = connect;
+        self.first_byte = first_byte;
+        self
+    }
+
+    /// When the MicroVM was due to be restored, relative to the epoch of the run.
+    pub fn arrival(&self) -> Duration {
+        self.arrival
+    }
+
+    /// When the restore of the MicroVM actually began, relative to the epoch of the run.
+    pub fn start(&self) -> Duration {
+        self.start
+    }
+
+    /// Set when the MicroVM was due to be restored and when its restore actually began, both
+    /// relative to the epoch of the run.
+    pub fn with_arrival(mut self, arrival: Duration, start: Duration) -> Self {
+        self.arrival = arrival;
+        self.start = start;
+        self
+    }
+
+    /// The outcome of setting the guest's clock right after resuming it, if it was set.
+    pub fn clock(&self) -> Option<ClockSync> {
+        self.clock
+    }
+
+    /// Set the outcome of setting the guest's clock right after resuming it.
+    pub fn with_clock(mut self, clock: Option<ClockSync>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// When each phase began and ended, relative to the epoch of the run.
+    pub fn timeline(&self) -> &Timeline {
+        &self.timeline
+    }
+
+    /// Set when each phase began and ended.
+    pub fn with_timeline(mut self, timeline: Timeline) -> Self {
+        self.timeline = timeline;
+        self
+    }
+
+    /// The delays associated with the 'cold-start' request.
+    pub fn cold(&self) -> &Delays {
+        &self.cold
+    }
+
+    /// The delays associated with the 'warm' request.
+    pub fn warm(&self) -> &Delays {
+        &self.warm
+    }
+
+    /// All durations of the `Measurement`, in the order of [`output::CSV_COLUMNS`].
+    pub fn durations(&self) -> [Duration; output::CSV_COLUMNS.len()] {
+        [
+            self.global,
+            self.restore,
+            self.resume,
+            self.cold.client,
+            self.cold.server,
+            self.warm.client,
+            self.warm.server,
+            self.connect,
+            self.first_byte,
+        ]
+    }
+
+    /// Returns an object that displays the `Measurement` as a CSV row, with all durations
+    /// expressed in the given [`TimeUnit`].
+    pub fn display_in(&self, unit: TimeUnit) -> output::DisplayIn<'_> {
+        output::DisplayIn::new(self, unit)
+    }
+}
+
 impl fmt::Display for Measurement {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{},{},{},{},{},{},{}",
-            self.global.as_micros(),
-            self.restore.as_micros(),
-            self.resume.as_micros(),
-            self.cold.client.as_micros(),
-            self.cold.server.as_micros(),
-            self.warm.client.as_micros(),
-            self.warm.server.as_micros()
-        )
+        self.display_in(TimeUnit::Micros).fmt(f)
     }
 }
 
 /// Represents the result from issuing a single request (be it cold or warm) using one of the rpc
-/// functions ([`zero_args_rpc`], [`one_arg_rpc`] and [`two_args_rpc`]).
+/// functions ([`zero_args_rpc`], [`one_arg_rpc`], [`two_args_rpc`] and
+/// [`string_arg_rpc`]).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Delays {
     /// The delay as measured by the client (i.e., it should include server's delay).
     client: Duration,
@@ -76,55 +226,61 @@ pub struct Delays {
     server: Duration,
 }
 
-impl From<(Duration, ServiceResponse)> for Delays {
-    fn from((client, resp): (Duration, ServiceResponse)) -> Self {
-        Self {
-            client,
-            server: resp
-                .response_duration
-                .unwrap_or_default()
-                .try_into()
-                .unwrap_or(Duration::ZERO),
-        }
+impl Delays {
+    /// The delay as measured by the client.
+    pub fn client(&self) -> Duration {
+        self.client
+    }
+
+    /// The delay as measured by the server.
+    pub fn server(&self) -> Duration {
+        self.server
     }
 }
 
-pub async fn zero_args_rpc(server_addr: String) -> Result<(Duration, ServiceResponse)> {
-    let mut client = ZeroArgumentsClient::connect(server_addr).await?;
-    let req = tonic::Request::new(());
+impl TryFrom<(Duration, ServiceResponse)> for Delays {
+    type Error = Error;
 
-    // Issue the request & time it
-    let client_start = Instant::now();
-    let resp = client.bench(req).await?;
-    let client_end = Instant::now();
+    /// Fails with [`Error::MalformedResponse`] if the server did not report a valid duration.
+    fn try_from((client, resp): (Duration, ServiceResponse)) -> Result<Self> {
+        let server = resp
+            .response_duration
+            .and_then(|server| server.try_into().ok())
+            .ok_or(Error::MalformedResponse)?;
+        Ok(Self { client, server })
+    }
+}
 
-    Ok((client_end - client_start, resp.into_inner()))
+/// Issue a request to the `ZeroArguments` service at `server_addr`, over a new connection.
+pub async fn zero_args_rpc(server_addr: String) -> Result<(Duration, ServiceResponse)> {
+    BenchClient::new(server_addr)?.bench(&RpcArgs::Zero).await
 }
 
+/// Issue a request to the `OneArgument` service at `server_addr`, over a new connection.
 pub async fn one_arg_rpc(server_addr: String, arg: u64) -> Result<(Duration, ServiceResponse)> {
-    let mut client = OneArgumentClient::connect(server_addr).await?;
-    let req = tonic::Request::new(fbpml_rpc::OneArgumentRequest { arg });
-
-    // Issue the request & time it
-    let client_start = Instant::now();
-    let resp = client.bench(req).await?;
-    let client_end = Instant::now();
-
-    Ok((client_end - client_start, resp.into_inner()))
+    BenchClient::new(server_addr)?
+        .bench(&RpcArgs::One(arg))
+        .await
 }
 
+/// Issue a request to the `TwoArguments` service at `server_addr`, over a new connection.
 pub async fn two_args_rpc(
     server_addr: String,
     arg1: u64,
     arg2: u64,
 ) -> Result<(Duration, ServiceResponse)> {
-    let mut client = TwoArgumentsClient::connect(server_addr).await?;
-    let req = tonic::Request::new(TwoArgumentsRequest { arg1, arg2 });
-
-    // Issue the request & time it
-    let client_start = Instant::now();
-    let resp = client.bench(req).await?;
-    let client_end = Instant::now();
+    BenchClient::new(server_addr)?
+        .bench(&RpcArgs::Two(arg1, arg2))
+        .await
+}
 
-    Ok((client_end - client_start, resp.into_inner()))
+/// Issue a request to the `StringArgument` service at `server_addr`, over a new connection.
+pub async fn string_arg_rpc(
+    server_addr: String,
+    arg: String,
+    len: u64,
+) -> Result<(Duration, ServiceResponse)> {
+    BenchClient::new(server_addr)?
+        .bench(&RpcArgs::Str(arg, len))
+        .await
 }
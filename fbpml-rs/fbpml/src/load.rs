@@ -0,0 +1,190 @@
+//! Open-loop load generation against a single MicroVM.
+//!
+//! Unlike the lockstep cold/warm requests of [`multi`](crate::multi), requests are issued at a
+//! target rate (with constant or Poisson inter-arrival times) for a given duration, regardless of
+//! whether earlier ones have been served yet. The latency of every request is recorded into an HDR
+//! histogram, so that the tail of the warm path can be studied under load.
+
+use std::{fmt, str::FromStr, time::Duration};
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use hdrhistogram::Histogram;
+use rand::{prelude::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+use tokio::time::{sleep_until, Instant};
+
+use crate::{BenchClient, RpcArgs};
+
+/// The highest latency that can be recorded; higher ones are clamped to it.
+const MAX_LATENCY: Duration = Duration::from_secs(60);
+/// The number of significant decimal digits retained for each recorded latency.
+const SIGNIFICANT_DIGITS: u8 = 3;
+
+/// How the arrival times of the requests are spaced.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Arrivals {
+    /// Requests arrive at fixed intervals; this is the default.
+    #[default]
+    Constant,
+    /// Requests arrive as a Poisson process (i.e., with exponentially distributed intervals).
+    Poisson,
+}
+
+impl Arrivals {
+    /// The interval until the next arrival, for the given mean `rate` (in requests per second).
+    fn interval(self, rate: f64, rng: &mut impl Rng) -> Duration {
+        match self {
+            Self::Constant => Duration::from_secs_f64(1.0 / rate),
+            Self::Poisson => Duration::from_secs_f64(-(1.0 - rng.gen::<f64>()).ln() / rate),
+        }
+    }
+}
+
+impl FromStr for Arrivals {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "constant" => Ok(Self::Constant),
+            "poisson" => Ok(Self::Poisson),
+            _ => Err(format!(
+                "unknown arrival process '{s}' (expected one of: constant, poisson)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Arrivals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Constant => "constant",
+            Self::Poisson => "poisson",
+        })
+    }
+}
+
+/// The parameters of an open-loop load.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadOpts {
+    /// The (mean) number of requests to issue per second.
+    pub rate: f64,
+    /// For how long to keep issuing requests.
+    pub duration: Duration,
+    /// How the arrival times of the requests are spaced.
+    pub arrivals: Arrivals,
+}
+
+/// The outcome of an open-loop load: the latencies of all requests that were served, in
+/// nanoseconds, along with the number of those that failed.
+#[derive(Debug, Clone)]
+pub struct LoadReport {
+    latencies: Histogram<u64>,
+    errors: u64,
+    elapsed: Duration,
+}
+
+impl Default for LoadReport {
+    fn default() -> Self {
+        Self {
+            latencies: Histogram::new_with_bounds(
+                1,
+                MAX_LATENCY.as_nanos() as u64,
+                SIGNIFICANT_DIGITS,
+            )
+            .expect("the bounds of the histogram are valid"),
+            errors: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+impl LoadReport {
+    fn record(&mut self, latency: Duration) {
+        self.latencies.saturating_record(latency.as_nanos() as u64);
+    }
+
+    /// Merge `other` into this report (e.g., to aggregate the reports of multiple MicroVMs that
+    /// were loaded concurrently).
+    pub fn merge(&mut self, other: &Self) {
+        self.latencies
+            .add(&other.latencies)
+            .expect("the histograms of all reports have the same bounds");
+        self.errors += other.errors;
+        self.elapsed = self.elapsed.max(other.elapsed);
+    }
+
+    /// The latency at the given quantile (e.g., `0.99` for p99).
+    pub fn quantile(&self, quantile: f64) -> Duration {
+        Duration::from_nanos(self.latencies.value_at_quantile(quantile))
+    }
+
+    /// Summarize the report into its throughput and the percentiles of its latencies.
+    pub fn summary(&self) -> LoadSummary {
+        let served = self.latencies.len();
+        LoadSummary {
+            requests: served + self.errors,
+            errors: self.errors,
+            elapsed: self.elapsed,
+            throughput: match self.elapsed.as_secs_f64() {
+                secs if secs > 0.0 => served as f64 / secs,
+                _ => 0.0,
+            },
+            p50: self.quantile(0.5),
+            p90: self.quantile(0.9),
+            p99: self.quantile(0.99),
+            p99_9: self.quantile(0.999),
+            max: Duration::from_nanos(self.latencies.max()),
+        }
+    }
+}
+
+/// A summary of a [`LoadReport`].
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct LoadSummary {
+    /// The number of requests issued.
+    pub requests: u64,
+    /// The number of requests that failed.
+    pub errors: u64,
+    /// The time from the first request until the last response.
+    pub elapsed: Duration,
+    /// The number of requests served per second.
+    pub throughput: f64,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub p99_9: Duration,
+    pub max: Duration,
+}
+
+/// Issue requests with the given arguments through `client`, according to `opts`, and wait for
+/// all of them to be served (or to fail).
+///
+/// The latency of each request is measured from the moment it was due (rather than from when it
+/// was actually issued), so that any lag of the client in keeping up with the target rate is not
+/// hidden from the results (i.e., it avoids coordinated omission). All requests are issued over
+/// the same connection, hence `client` should have been connected beforehand.
+pub async fn open_loop(client: &BenchClient, args: &RpcArgs, opts: LoadOpts) -> LoadReport {
+    let mut report = LoadReport::default();
+    let mut rng: StdRng = SeedableRng::from_entropy();
+    let mut in_flight = FuturesUnordered::new();
+
+    let start = Instant::now();
+    let end = start + opts.duration;
+    let mut next = start;
+    loop {
+        tokio::select! {
+            _ = sleep_until(next), if next < end => {
+                let due = next;
+                in_flight.push(async move { (due, client.bench(args).await) });
+                next += opts.arrivals.interval(opts.rate, &mut rng);
+            }
+            Some((due, res)) = in_flight.next() => match res {
+                Ok(_) => report.record(Instant::now() - due),
+                Err(_) => report.errors += 1,
+            },
+            else => break,
+        }
+    }
+    report.elapsed = Instant::now() - start;
+    report
+}
@@ -0,0 +1,295 @@
+//! The manifest that `build-snapshots` stores next to the snapshots it creates (as
+//! `manifest.json`), describing how they were built.
+//!
+//! It records the benchmark and the configuration of the MicroVMs that were snapshotted, along with
+//! the size and CRC32 of each snapshot file, so that clients can refuse to restore from snapshots
+//! that were built for another benchmark or that have changed since (see [`Manifest::check`]).
+//! Only the sizes of the files are checked before restoring, since reading them to verify their
+//! checksums would bring them into the page cache.
+//!
+//! The creation time of each snapshot is recorded as well, since a restored guest's clock is as of
+//! when its snapshot was created: benchmarks that fetch their input from MinIO fail once it lags
+//! behind by more than [`MINIO_MAX_SKEW`] (see [`SnapshotEntry::check_fresh`]).
+
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bench::Benchmark;
+
+/// The name of the manifest, in the directory of the snapshots it describes.
+pub const MANIFEST: &str = "manifest.json";
+
+/// The maximum clock skew that MinIO tolerates between itself and its clients.
+pub const MINIO_MAX_SKEW: Duration = Duration::from_secs(15 * 60);
+
+/// The size of the chunks in which files are read to be checksummed.
+const CHUNK: usize = 1 << 21;
+
+/// Errors that may occur while reading, writing or checking a manifest.
+#[derive(Debug, thiserror::Error)]
+pub enum ManifestError {
+    /// The manifest (or one of the files it describes) could not be accessed.
+    #[error("failed to {op} '{}': {source}", .path.display())]
+    Io {
+        op: &'static str,
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// The manifest is not valid JSON or lacks some fields.
+    #[error("malformed snapshot manifest '{}': {source}", .path.display())]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    /// The snapshots were built for another benchmark.
+    #[error("'{}' describes snapshots of '{found}', not of '{expected}'", .path.display())]
+    BenchMismatch {
+        path: PathBuf,
+        expected: &'static str,
+        found: String,
+    },
+
+    /// The manifest does not describe the given snapshot file.
+    #[error("'{}' does not describe '{}'", .path.display(), .file.display())]
+    Unknown { path: PathBuf, file: PathBuf },
+
+    /// A snapshot file has changed since it was described in the manifest.
+    #[error("'{}' is {found} bytes long, but {expected} bytes as per its manifest", .path.display())]
+    SizeMismatch {
+        path: PathBuf,
+        expected: u64,
+        found: u64,
+    },
+
+    /// The snapshot is too old for its benchmark to reach MinIO once restored.
+    #[error(
+        "'{}' was created {}s ago, hence MinIO would reject the restored guest's clock",
+        .path.display(),
+        .age.as_secs()
+    )]
+    Stale { path: PathBuf, age: Duration },
+}
+
+/// The description of a directory of snapshots, as created by `build-snapshots`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The name of the benchmark (e.g., `matmul_fb`).
+    pub bench: String,
+    /// The guest memory size of the MicroVMs, in MiB.
+    pub mem_size_mib: u64,
+    pub vcpu_count: u64,
+    pub kernel_image: PathBuf,
+    pub firecracker: PathBuf,
+    /// The output of `firecracker --version`.
+    pub firecracker_version: String,
+    /// The number of invocations issued to each MicroVM before its full snapshot was created.
+    #[serde(default)]
+    pub warm_invocations: usize,
+    /// The arguments of all warm-up invocations; empty for the benchmark's default ones.
+    #[serde(default)]
+    pub bench_args: Vec<String>,
+    /// `K` for the `K`-th diff snapshots (see [`diff`](crate::diff)); `None` for full ones.
+    #[serde(default)]
+    pub diff: Option<usize>,
+    /// The snapshots of all MicroVMs, by ID.
+    pub snapshots: Vec<SnapshotEntry>,
+}
+
+/// The description of the snapshot of a single MicroVM.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    pub id: usize,
+    pub rootfs: PathBuf,
+    /// When the snapshot was created, in milliseconds since the Unix epoch.
+    pub created_unix_ms: u64,
+    pub state: FileEntry,
+    /// The memory file to restore from; for diff snapshots, the one layered on top of its base.
+    pub memory: FileEntry,
+    /// The memory file of a diff snapshot, as written by Firecracker.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layer: Option<FileEntry>,
+}
+
+/// The description of a snapshot file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    /// The name of the file, in the directory of the manifest.
+    pub name: String,
+    pub len: u64,
+    pub crc32: u32,
+}
+
+impl FileEntry {
+    /// Describe the file at `path`, reading it all to checksum it.
+    pub fn of(path: &Path) -> Result<Self, ManifestError> {
+        let io_error = |op| {
+            move |source| ManifestError::Io {
+                op,
+                path: path.to_path_buf(),
+                source,
+            }
+        };
+        let mut file = File::open(path).map_err(io_error("open"))?;
+        let len = file.metadata().map_err(io_error("stat"))?.len();
+        let mut buf = vec![0; CHUNK];
+        let mut hasher = crc32fast::Hasher::new();
+        loop {
+            match file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(nr) => hasher.update(&buf[..nr]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(io_error("read")(err)),
+            }
+        }
+        Ok(Self {
+            name: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            len,
+            crc32: hasher.finalize(),
+        })
+    }
+
+    /// Make sure that the file at `path` still has the size it was described with.
+    fn check_len(&self, path: &Path) -> Result<(), ManifestError> {
+        let found = fs::metadata(path)
+            .map_err(|source| ManifestError::Io {
+                op: "stat",
+                path: path.to_path_buf(),
+                source,
+            })?
+            .len();
+        if found != self.len {
+            return Err(ManifestError::SizeMismatch {
+                path: path.to_path_buf(),
+                expected: self.len,
+                found,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl SnapshotEntry {
+    /// When the snapshot was created (i.e., the time the restored guest's clock is going to show).
+    pub fn created(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.created_unix_ms)
+    }
+
+    /// How long ago the snapshot was created.
+    pub fn age(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(self.created())
+            .unwrap_or_default()
+    }
+
+    /// Make sure that the snapshot, whose state file is at `state`, is recent enough for `bench`
+    /// to reach MinIO once restored; snapshots of benchmarks that do not need MinIO never expire.
+    pub fn check_fresh(&self, bench: &Benchmark, state: &Path) -> Result<(), ManifestError> {
+        let age = self.age();
+        if bench.needs_minio && age >= MINIO_MAX_SKEW {
+            return Err(ManifestError::Stale {
+                path: state.to_path_buf(),
+                age,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Manifest {
+    /// Load the manifest stored in `dir`, if there is one.
+    pub fn load(dir: &Path) -> Result<Option<Self>, ManifestError> {
+        let path = dir.join(MANIFEST);
+        match fs::read(&path) {
+            Ok(buf) => serde_json::from_slice(&buf)
+                .map(Some)
+                .map_err(|source| ManifestError::Parse { path, source }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(source) => Err(ManifestError::Io {
+                op: "read",
+                path,
+                source,
+            }),
+        }
+    }
+
+    /// Store the manifest in `dir`.
+    pub fn save(&self, dir: &Path) -> Result<(), ManifestError> {
+        let path = dir.join(MANIFEST);
+        let buf = serde_json::to_vec_pretty(self).map_err(|source| ManifestError::Parse {
+            path: path.clone(),
+            source,
+        })?;
+        fs::write(&path, buf).map_err(|source| ManifestError::Io {
+            op: "write",
+            path,
+            source,
+        })
+    }
+
+    /// Load the manifest that lies next to the snapshot's `state` file, if there is one, and
+    /// [`check`](Manifest::check) the snapshot against it, returning its entry.
+    pub fn load_and_check(
+        bench: &Benchmark,
+        state: &Path,
+        memory: &Path,
+    ) -> Result<Option<SnapshotEntry>, ManifestError> {
+        let dir = state.parent().unwrap_or_else(|| Path::new("."));
+        match Self::load(dir)? {
+            Some(manifest) => manifest
+                .check(&dir.join(MANIFEST), bench, state, memory)
+                .map(|entry| Some(entry.clone())),
+            None => Ok(None),
+        }
+    }
+
+    /// Make sure that the snapshot consisting of the given `state` and `memory` files was built
+    /// for `bench`, and that neither of its files has changed size since; `path` is the path of
+    /// the manifest itself, to be reported in case of errors.
+    pub fn check(
+        &self,
+        path: &Path,
+        bench: &Benchmark,
+        state: &Path,
+        memory: &Path,
+    ) -> Result<&SnapshotEntry, ManifestError> {
+        if self.bench != bench.name {
+            return Err(ManifestError::BenchMismatch {
+                path: path.to_path_buf(),
+                expected: bench.name,
+                found: self.bench.clone(),
+            });
+        }
+        let is = |entry: &FileEntry, file: &Path| file.file_name() == Some(entry.name.as_ref());
+        let entry = self
+            .snapshots
+            .iter()
+            .find(|s| is(&s.state, state))
+            .ok_or_else(|| ManifestError::Unknown {
+                path: path.to_path_buf(),
+                file: state.to_path_buf(),
+            })?;
+        if !is(&entry.memory, memory) {
+            return Err(ManifestError::Unknown {
+                path: path.to_path_buf(),
+                file: memory.to_path_buf(),
+            });
+        }
+        entry.state.check_len(state)?;
+        entry.memory.check_len(memory)?;
+        Ok(entry)
+    }
+}
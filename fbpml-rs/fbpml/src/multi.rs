@@ -0,0 +1,394 @@
+//! Restoring, resuming and benchmarking multiple MicroVMs in lockstep.
+//!
+//! Each MicroVM is driven by its own worker task; all workers rendezvous on a shared [`Barrier`]
+//! between consecutive phases, so that e.g. all MicroVMs are restored concurrently, then all of
+//! them are resumed concurrently, and so on.
+//!
+//! Alternatively, the restores may be spread in time according to a [`RestoreArrivals`] policy,
+//! in which case each MicroVM is restored, resumed and sent its cold request on its own, and the
+//! workers only rendezvous again before the warm request.
+
+use std::{fmt, str::FromStr, sync::Arc, time::Duration};
+
+use rand::{prelude::StdRng, Rng, SeedableRng};
+use tokio::{
+    sync::{Barrier, Semaphore},
+    time::{sleep, sleep_until, Instant},
+};
+
+use crate::{
+    cache::{ResidencyReport, SnapshotFiles},
+    firecracker::SnapshotLoadParams,
+    timeline::{Epoch, Phase, Timeline},
+    BenchClient, Error, FirecrackerApi, Measurement, Result, RpcArgs,
+};
+
+/// The number of times [`restore_and_bench`] waits on the shared [`Barrier`].
+pub const RESTORE_RENDEZVOUS: usize = COLD_RENDEZVOUS + 2;
+
+/// The number of times [`restore_and_bench`] waits on the shared [`Barrier`] up to the cold
+/// request (i.e., before restoring, after restoring, after resuming and after the cold request);
+/// unless the MicroVMs proceed in lockstep, those after restoring and resuming are made up for
+/// after the cold request.
+const COLD_RENDEZVOUS: usize = 4;
+
+/// Wraps the [`Barrier`] shared among all worker tasks, keeping track of how many times the worker
+/// has waited on it, so that a worker that fails early can still participate in all remaining
+/// rendezvous points, rather than leaving all others blocked forever.
+pub struct Rendezvous {
+    barrier: Arc<Barrier>,
+    passed: usize,
+}
+
+impl Rendezvous {
+    pub fn new(barrier: Arc<Barrier>) -> Self {
+        Self { barrier, passed: 0 }
+    }
+
+    pub async fn wait(&mut self) {
+        self.barrier.wait().await;
+        self.passed += 1;
+    }
+
+    /// Keep waiting on the barrier until it has been passed `total` times.
+    pub async fn finish(&mut self, total: usize) {
+        while self.passed < total {
+            self.wait().await;
+        }
+    }
+}
+
+/// The options that affect the behavior of each worker task.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WorkerOpts {
+    /// Number of warm requests to issue (after the cold one) before the warm request that is
+    /// actually going to be reported.
+    pub pre_warm: usize,
+    /// Whether the connection established for the cold request is reused for all subsequent ones.
+    pub reuse_connection: bool,
+    /// Whether the guest's clock is set right before the cold request, over the same connection
+    /// (see [`BenchClient::sync_clock`]); this is excluded from the global duration.
+    pub sync_clock: bool,
+    /// The time after which each request (or connection attempt) is abandoned as timed out, if
+    /// any (see [`BenchClient::timeout`]).
+    pub timeout: Option<Duration>,
+}
+
+/// How the restores of the MicroVMs of a run are spread in time.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum RestoreArrivals {
+    /// All MicroVMs are restored at the same instant, and then proceed in lockstep (i.e., the
+    /// "thundering herd"); this is the default.
+    #[default]
+    Simultaneous,
+    /// MicroVM `i` is restored `i` times the given interval after the first one.
+    Stagger(Duration),
+    /// The restores arrive as a Poisson process of the given mean rate (per second).
+    Poisson(f64),
+    /// All MicroVMs arrive at the same instant, but at most the given number of them are being
+    /// restored at any time; i.e., from loading their snapshot until their cold request has been
+    /// served, since their memory is only paged in lazily, while serving it.
+    Concurrent(usize),
+}
+
+impl FromStr for RestoreArrivals {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid restore arrivals '{s}' (expected one of: simultaneous, stagger:<MILLIS>, \
+                 poisson:<RATE>, concurrent:<K>)"
+            )
+        };
+        let (policy, param) = s.split_once(':').unwrap_or((s, ""));
+        match (policy, param) {
+            ("simultaneous", "") => Some(Self::Simultaneous),
+            ("stagger", millis) => millis
+                .parse::<f64>()
+                .ok()
+                .and_then(|millis| Duration::try_from_secs_f64(millis / 1000.0).ok())
+                .map(Self::Stagger),
+            ("poisson", rate) => rate
+                .parse()
+                .ok()
+                .filter(|rate: &f64| rate.is_finite() && *rate > 0.0)
+                .map(Self::Poisson),
+            ("concurrent", k) => k.parse().ok().filter(|&k| k > 0).map(Self::Concurrent),
+            _ => None,
+        }
+        .ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for RestoreArrivals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Simultaneous => f.write_str("simultaneous"),
+            Self::Stagger(interval) => write!(f, "stagger:{}", interval.as_secs_f64() * 1000.0),
+            Self::Poisson(rate) => write!(f, "poisson:{rate}"),
+            Self::Concurrent(k) => write!(f, "concurrent:{k}"),
+        }
+    }
+}
+
+/// The restore arrivals of all MicroVMs of a run, as drawn from a [`RestoreArrivals`] policy.
+#[derive(Debug)]
+pub struct RestoreSchedule {
+    arrivals: RestoreArrivals,
+    offsets: Vec<Duration>,
+    permits: Option<Arc<Semaphore>>,
+    epoch: Epoch,
+}
+
+impl RestoreSchedule {
+    /// Draw the arrivals of MicroVMs `0..num_uvms` from the given policy.
+    pub fn new(arrivals: RestoreArrivals, num_uvms: usize) -> Self {
+        let offsets = match arrivals {
+            RestoreArrivals::Stagger(interval) => {
+                (0..num_uvms).map(|id| interval * id as u32).collect()
+            }
+            RestoreArrivals::Poisson(rate) => {
+                let mut rng: StdRng = SeedableRng::from_entropy();
+                let mut offset = Duration::ZERO;
+                (0..num_uvms)
+                    .map(|_| {
+                        let arrival = offset;
+                        offset += Duration::from_secs_f64(-(1.0 - rng.gen::<f64>()).ln() / rate);
+                        arrival
+                    })
+                    .collect()
+            }
+            RestoreArrivals::Simultaneous | RestoreArrivals::Concurrent(_) => {
+                vec![Duration::ZERO; num_uvms]
+            }
+        };
+        let permits = match arrivals {
+            RestoreArrivals::Concurrent(k) => Some(Arc::new(Semaphore::new(k))),
+            _ => None,
+        };
+        Self {
+            arrivals,
+            offsets,
+            permits,
+            epoch: Epoch::default(),
+        }
+    }
+
+    /// The epoch that all arrivals are relative to.
+    pub fn epoch(&self) -> &Epoch {
+        &self.epoch
+    }
+
+    /// The arrival of MicroVM `id`, to be passed to its worker task.
+    pub fn arrival(&self, id: usize) -> Arrival {
+        Arrival {
+            offset: self.offsets[id],
+            lockstep: self.arrivals == RestoreArrivals::Simultaneous,
+            permits: self.permits.clone(),
+            epoch: self.epoch.clone(),
+        }
+    }
+}
+
+/// When a worker task is to restore its MicroVM, relative to the [`Epoch`] shared by all workers
+/// of the run (i.e., the instant they all passed the first rendezvous).
+#[derive(Debug, Clone)]
+pub struct Arrival {
+    offset: Duration,
+    lockstep: bool,
+    permits: Option<Arc<Semaphore>>,
+    epoch: Epoch,
+}
+
+#[derive(Debug, Clone)]
+pub struct RestoreTarget {
+    pub api: FirecrackerApi,
+    pub snapshot: SnapshotLoadParams,
+}
+
+/// Issue `rounds` warm requests, each followed by a short random pause; returns when each request
+/// was issued and when its response arrived.
+pub async fn pre_warm(
+    client: &BenchClient,
+    args: &RpcArgs,
+    rounds: usize,
+) -> Result<Vec<(Instant, Instant)>> {
+    if rounds == 0 {
+        return Ok(Vec::new());
+    }
+    let mut rng: StdRng = SeedableRng::from_entropy();
+    let mut issued = Vec::with_capacity(rounds);
+    for _ in 0..rounds {
+        let start = Instant::now();
+        let _ = client.bench(args).await?;
+        issued.push((start, Instant::now()));
+        sleep(Duration::from_millis(rng.gen_range(20..120))).await;
+    }
+    Ok(issued)
+}
+
+/// A standalone worker task's routine for restoring the MicroVM of `target` from its snapshot
+/// upon its `arrival`, resuming it, and then issuing the cold and the warm request to it through
+/// `client`.
+///
+/// All validation and allocations are expected to have taken place before calling it, since the
+/// global timer begins right after the MicroVM's arrival. Unless all MicroVMs arrive
+/// simultaneously, the restore, resume and cold request of each one are not synchronized with
+/// those of others. The [`Timeline`] of all phases is recorded in the resulting [`Measurement`].
+pub async fn restore_and_bench(
+    target: &RestoreTarget,
+    mut client: BenchClient,
+    args: &RpcArgs,
+    opts: WorkerOpts,
+    arrival: &Arrival,
+    barrier: &mut Rendezvous,
+) -> Result<Measurement> {
+    barrier.wait().await;
+
+    // Wait for the uVM's turn (if the restores are spread in time or limited in number)
+    let epoch = arrival.epoch.get();
+    sleep_until(epoch + arrival.offset).await;
+    let permit = match &arrival.permits {
+        Some(permits) => Some(
+            permits
+                .acquire()
+                .await
+                .expect("the semaphore is never closed"),
+        ),
+        None => None,
+    };
+
+    // Start the global timer and restore the uVM from the snapshot
+    let mut timeline = Timeline::default();
+    let global_start = Instant::now();
+    let restore = target
+        .api
+        .load_snapshot(&target.snapshot)
+        .await
+        .map_err(Error::SnapshotLoad)?;
+    timeline.record(Phase::Restore, epoch, global_start, Instant::now());
+    if arrival.lockstep {
+        barrier.wait().await;
+    }
+
+    // Resume the uVM restored from the snapshot
+    let start = Instant::now();
+    let resume = target.api.resume().await.map_err(Error::Resume)?;
+    timeline.record(Phase::Resume, epoch, start, Instant::now());
+    if arrival.lockstep {
+        barrier.wait().await;
+    }
+
+    // Connect, set the guest's clock over the same connection (if requested, excluding it from
+    // the global timer), issue the "cold" request and stop the global timer
+    let start = Instant::now();
+    let connect = client.connect().await?;
+    let end = Instant::now();
+    timeline.record(Phase::Connect, epoch, start, end);
+    let (clock, clock_elapsed) = if opts.sync_clock {
+        let clock = client.sync_clock().await?;
+        let sync_end = Instant::now();
+        timeline.record(Phase::SyncClock, epoch, end, sync_end);
+        (Some(clock), sync_end - end)
+    } else {
+        (None, Duration::ZERO)
+    };
+    let cold_start = Instant::now();
+    let (cold_timing, cold) = client.bench_timed(args).await?;
+    let cold_end = Instant::now();
+    timeline.record(Phase::Cold, epoch, cold_start, cold_end);
+    let global = cold_end - global_start - clock_elapsed;
+    drop(permit);
+    if !opts.reuse_connection {
+        client.disconnect();
+    }
+    // Catch up on the rendezvous skipped above, so that all warm requests are still synchronized
+    barrier.finish(COLD_RENDEZVOUS).await;
+
+    // Asynchronously pre-warm in parallel, if necessary
+    for (start, end) in pre_warm(&client, args, opts.pre_warm).await? {
+        timeline.record(Phase::PreWarm, epoch, start, end);
+    }
+    barrier.wait().await;
+
+    // Issue the "warm" request
+    let start = Instant::now();
+    let warm = client.bench(args).await?;
+    timeline.record(Phase::Warm, epoch, start, Instant::now());
+    barrier.wait().await;
+
+    let m: Measurement = (
+        global,
+        restore,
+        resume,
+        (cold_timing.total, cold).try_into()?,
+        warm.try_into()?,
+    )
+        .into();
+    Ok(m.with_connection(connect, cold_timing.first_byte)
+        .with_arrival(arrival.offset, global_start - epoch)
+        .with_clock(clock)
+        .with_timeline(timeline))
+}
+
+/// Like [`restore_and_bench`], but also report how much of the MicroVM's `files` resided in the
+/// page cache right before it was restored and after the warm request had been served.
+///
+/// Both probes take place outside of all timed phases (i.e., before the first and after the last
+/// rendezvous), so that they do not affect the measurements.
+pub async fn restore_and_bench_probed(
+    target: &RestoreTarget,
+    client: BenchClient,
+    args: &RpcArgs,
+    opts: WorkerOpts,
+    arrival: &Arrival,
+    files: &SnapshotFiles,
+    barrier: &mut Rendezvous,
+) -> Result<(Measurement, ResidencyReport)> {
+    let pre_restore = files.probe()?;
+    let m = restore_and_bench(target, client, args, opts, arrival, barrier).await?;
+    let post_warm = files.probe()?;
+    Ok((
+        m,
+        ResidencyReport {
+            pre_restore,
+            post_warm,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_restore_arrivals() {
+        for arrivals in [
+            RestoreArrivals::Simultaneous,
+            RestoreArrivals::Stagger(Duration::from_millis(10)),
+            RestoreArrivals::Stagger(Duration::from_micros(2500)),
+            RestoreArrivals::Poisson(0.5),
+            RestoreArrivals::Concurrent(4),
+        ] {
+            assert_eq!(arrivals.to_string().parse(), Ok(arrivals));
+        }
+        assert_eq!(
+            "stagger:0".parse(),
+            Ok(RestoreArrivals::Stagger(Duration::ZERO))
+        );
+        for s in [
+            "",
+            "simultaneous:1",
+            "stagger",
+            "stagger:-1",
+            "poisson:0",
+            "poisson:inf",
+            "concurrent:0",
+            "concurrent:1.5",
+            "lockstep",
+        ] {
+            assert!(s.parse::<RestoreArrivals>().is_err(), "{s}");
+        }
+    }
+}
@@ -0,0 +1,856 @@
+//! Machine-readable output of [`Measurement`]s, [`LoadSummary`]s, [`InvocationRecord`]s and
+//! [`Timeline`]s.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    cache::ResidencyReport,
+    fcmetrics::RestoreLatencies,
+    load::LoadSummary,
+    replay::InvocationRecord,
+    timeline::{Event, MemorySample, Timeline},
+    ClockSync, Measurement,
+};
+
+/// The names of the columns of a [`Measurement`] in CSV format, in the order they are printed.
+pub const CSV_COLUMNS: &[&str] = &[
+    "global",
+    "restore",
+    "resume",
+    "cold_client",
+    "cold_server",
+    "warm_client",
+    "warm_server",
+    "connect",
+    "first_byte",
+];
+
+/// The names of the additional columns of a [`Measurement`] that place it on the timeline of a run
+/// of multiple MicroVMs (see [`Measurement::arrival`] and [`Measurement::start`]) in CSV format, in
+/// the order they are printed right after the [`CSV_COLUMNS`].
+pub const ARRIVAL_COLUMNS: &[&str] = &["arrival", "start"];
+
+/// The names of the additional columns of a [`ResidencyReport`] in CSV format, i.e. the number of
+/// resident pages of each file, in the order they are printed after those of the [`Measurement`].
+pub const RESIDENCY_COLUMNS: &[&str] = &[
+    "pre_state_pages",
+    "pre_memory_pages",
+    "pre_rootfs_pages",
+    "post_state_pages",
+    "post_memory_pages",
+    "post_rootfs_pages",
+];
+
+/// The names of the additional columns of the [`RestoreLatencies`] reported by Firecracker in CSV
+/// format, in the order they are printed after those of the [`Measurement`] (and of the
+/// [`ResidencyReport`], if any).
+pub const FC_LATENCY_COLUMNS: &[&str] = &[
+    "fc_load_snapshot",
+    "fc_vmm_load_snapshot",
+    "fc_resume_vm",
+    "fc_vmm_resume_vm",
+];
+
+/// The names of the additional columns of the [`ClockSync`] of a [`Measurement`] in CSV format, in
+/// the order they are printed after all others above.
+pub const CLOCK_COLUMNS: &[&str] = &["sync_clock", "clock_skew"];
+
+/// The names of the columns of a [`LoadSummary`] in CSV format, in the order they are printed.
+pub const LOAD_COLUMNS: &[&str] = &[
+    "requests",
+    "errors",
+    "elapsed",
+    "throughput",
+    "p50",
+    "p90",
+    "p99",
+    "p99_9",
+    "max",
+];
+
+/// The names of the columns of an [`InvocationRecord`] in CSV format, in the order they are
+/// printed.
+pub const INVOCATION_COLUMNS: &[&str] = &[
+    "index", "at", "function", "id", "start", "wait", "latency", "restore", "resume", "connect",
+    "client", "server", "error",
+];
+
+/// The names of the columns of each [`Event`] of a [`Timeline`] in CSV format, in the order they
+/// are printed.
+pub const TIMELINE_COLUMNS: &[&str] = &["id", "phase", "start", "end"];
+
+/// The supported output formats for [`Measurement`]s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Bare comma-separated values, one row per [`Measurement`], without a header.
+    Csv,
+    /// Comma-separated values, preceded by a header that names the columns.
+    CsvHeader,
+    /// A single JSON array of objects.
+    Json,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "csv-header" => Ok(Self::CsvHeader),
+            "json" => Ok(Self::Json),
+            "jsonl" => Ok(Self::JsonLines),
+            _ => Err(format!(
+                "unknown output format '{s}' (expected one of: csv, csv-header, json, jsonl)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Csv => "csv",
+            Self::CsvHeader => "csv-header",
+            Self::Json => "json",
+            Self::JsonLines => "jsonl",
+        })
+    }
+}
+
+/// The unit in which durations are expressed in CSV output.
+///
+/// Serialized (i.e., JSON) output is not affected, since it always retains the raw [`Duration`]s.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnit {
+    /// Whole nanoseconds.
+    Nanos,
+    /// Whole microseconds (truncated); this is the default.
+    #[default]
+    Micros,
+    /// Whole milliseconds (truncated).
+    Millis,
+    /// Fractional seconds, with nanosecond precision.
+    FractionalSecs,
+}
+
+impl TimeUnit {
+    /// Write `d` expressed in this unit; no floating point arithmetic is involved, so that
+    /// fractional values retain full nanosecond precision.
+    pub fn write_duration(self, f: &mut impl fmt::Write, d: Duration) -> fmt::Result {
+        match self {
+            Self::Nanos => write!(f, "{}", d.as_nanos()),
+            Self::Micros => write!(f, "{}", d.as_micros()),
+            Self::Millis => write!(f, "{}", d.as_millis()),
+            Self::FractionalSecs => write!(f, "{}.{:09}", d.as_secs(), d.subsec_nanos()),
+        }
+    }
+}
+
+impl FromStr for TimeUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ns" => Ok(Self::Nanos),
+            "us" | "µs" => Ok(Self::Micros),
+            "ms" => Ok(Self::Millis),
+            "s" => Ok(Self::FractionalSecs),
+            _ => Err(format!(
+                "unknown time unit '{s}' (expected one of: ns, us, ms, s)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TimeUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Nanos => "ns",
+            Self::Micros => "us",
+            Self::Millis => "ms",
+            Self::FractionalSecs => "s",
+        })
+    }
+}
+
+/// Displays a [`Measurement`] as a CSV row, in a specific [`TimeUnit`]; see
+/// [`Measurement::display_in`].
+pub struct DisplayIn<'a> {
+    measurement: &'a Measurement,
+    unit: TimeUnit,
+}
+
+impl<'a> DisplayIn<'a> {
+    pub(crate) fn new(measurement: &'a Measurement, unit: TimeUnit) -> Self {
+        Self { measurement, unit }
+    }
+}
+
+impl fmt::Display for DisplayIn<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, d) in self.measurement.durations().into_iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            self.unit.write_duration(f, d)?;
+        }
+        Ok(())
+    }
+}
+
+/// Displays a single [`Duration`] in a specific [`TimeUnit`].
+struct DisplayDuration {
+    d: Duration,
+    unit: TimeUnit,
+}
+
+impl fmt::Display for DisplayDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.unit.write_duration(f, self.d)
+    }
+}
+
+/// A single record of any of the writers of this module, which can be written in any
+/// [`OutputFormat`]: it is serialized as is for the JSON formats.
+trait Row: Serialize {
+    /// Write the record as a CSV row (without the trailing newline), with all durations expressed
+    /// in the given [`TimeUnit`].
+    fn write_csv(&self, w: &mut impl Write, unit: TimeUnit) -> io::Result<()>;
+}
+
+/// Writes [`Row`]s to the underlying writer, in the requested [`OutputFormat`]; all writers of
+/// this module (apart from [`TraceWriter`]) are built upon it.
+///
+/// [`RowWriter::finish`] must be called after all rows have been written, for the output to be
+/// complete (e.g., to close the JSON array).
+struct RowWriter<W: Write> {
+    w: W,
+    format: OutputFormat,
+    unit: TimeUnit,
+    /// The header of the CSV output (without the trailing newline).
+    header: String,
+    written: usize,
+}
+
+impl<W: Write> RowWriter<W> {
+    fn new(w: W, format: OutputFormat, header: String) -> Self {
+        Self {
+            w,
+            format,
+            unit: TimeUnit::default(),
+            header,
+            written: 0,
+        }
+    }
+
+    fn write(&mut self, row: &impl Row) -> io::Result<()> {
+        if self.written == 0 {
+            self.begin()?;
+        }
+        match self.format {
+            OutputFormat::Csv | OutputFormat::CsvHeader => {
+                row.write_csv(&mut self.w, self.unit)?;
+                writeln!(self.w)?;
+            }
+            OutputFormat::Json => {
+                if self.written > 0 {
+                    self.w.write_all(b",")?;
+                }
+                serde_json::to_writer(&mut self.w, row)?;
+            }
+            OutputFormat::JsonLines => {
+                serde_json::to_writer(&mut self.w, row)?;
+                self.w.write_all(b"\n")?;
+            }
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        if self.written == 0 {
+            self.begin()?;
+        }
+        if self.format == OutputFormat::Json {
+            self.w.write_all(b"]\n")?;
+        }
+        self.w.flush()
+    }
+
+    fn begin(&mut self) -> io::Result<()> {
+        match self.format {
+            OutputFormat::CsvHeader => writeln!(self.w, "{}", self.header),
+            OutputFormat::Json => self.w.write_all(b"["),
+            OutputFormat::Csv | OutputFormat::JsonLines => Ok(()),
+        }
+    }
+}
+
+/// The optional columns of the CSV output of a [`MeasurementWriter`].
+#[derive(Debug, Default, Clone, Copy)]
+struct MeasurementColumns {
+    id: bool,
+    arrivals: bool,
+    residency: bool,
+    fc_latencies: bool,
+    clock: bool,
+}
+
+impl MeasurementColumns {
+    fn header(self) -> String {
+        let mut header = Vec::new();
+        if self.id {
+            header.push("id");
+        }
+        header.extend(CSV_COLUMNS);
+        for (enabled, columns) in [
+            (self.arrivals, ARRIVAL_COLUMNS),
+            (self.residency, RESIDENCY_COLUMNS),
+            (self.fc_latencies, FC_LATENCY_COLUMNS),
+            (self.clock, CLOCK_COLUMNS),
+        ] {
+            if enabled {
+                header.extend(columns);
+            }
+        }
+        header.join(",")
+    }
+}
+
+/// A single record of the output; i.e., a [`Measurement`], possibly along with the ID of the
+/// MicroVM it refers to, a [`ResidencyReport`] of its files and the [`RestoreLatencies`] reported
+/// by Firecracker.
+#[derive(Serialize)]
+struct Record<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<usize>,
+    #[serde(flatten)]
+    measurement: &'a Measurement,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    residency: Option<&'a ResidencyReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    firecracker: Option<&'a RestoreLatencies>,
+    #[serde(skip)]
+    columns: MeasurementColumns,
+}
+
+impl Row for Record<'_> {
+    fn write_csv(&self, w: &mut impl Write, unit: TimeUnit) -> io::Result<()> {
+        let m = self.measurement;
+        if let Some(id) = self.id {
+            write!(w, "{id},")?;
+        }
+        write!(w, "{}", m.display_in(unit))?;
+        if self.columns.arrivals {
+            for d in [m.arrival(), m.start()] {
+                write!(w, ",{}", DisplayDuration { d, unit })?;
+            }
+        }
+        if self.columns.residency {
+            let pages = self
+                .residency
+                .map_or([None; 6], ResidencyReport::resident_pages);
+            for n in pages {
+                match n {
+                    Some(n) => write!(w, ",{n}")?,
+                    None => w.write_all(b",")?,
+                }
+            }
+        }
+        if self.columns.fc_latencies {
+            match self.firecracker {
+                Some(latencies) => {
+                    for d in latencies.durations() {
+                        write!(w, ",{}", DisplayDuration { d, unit })?;
+                    }
+                }
+                None => w.write_all(&b",".repeat(FC_LATENCY_COLUMNS.len()))?,
+            }
+        }
+        if self.columns.clock {
+            match m.clock() {
+                Some(ClockSync { duration, skew }) => {
+                    for d in [duration, skew] {
+                        write!(w, ",{}", DisplayDuration { d, unit })?;
+                    }
+                }
+                None => w.write_all(&b",".repeat(CLOCK_COLUMNS.len()))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes [`Measurement`]s to the underlying writer, in the requested [`OutputFormat`].
+///
+/// [`MeasurementWriter::finish`] must be called after all [`Measurement`]s have been written, for
+/// the output to be complete (e.g., to close the JSON array).
+pub struct MeasurementWriter<W: Write> {
+    rows: RowWriter<W>,
+    columns: MeasurementColumns,
+}
+
+impl<W: Write> MeasurementWriter<W> {
+    /// Create a new writer; `with_id` indicates whether each record is accompanied by the ID of
+    /// the MicroVM it refers to.
+    pub fn new(w: W, format: OutputFormat, with_id: bool) -> Self {
+        let columns = MeasurementColumns {
+            id: with_id,
+            ..Default::default()
+        };
+        Self {
+            rows: RowWriter::new(w, format, columns.header()),
+            columns,
+        }
+    }
+
+    /// Set the [`TimeUnit`] for CSV output (it is ignored by the JSON formats).
+    pub fn time_unit(mut self, unit: TimeUnit) -> Self {
+        self.rows.unit = unit;
+        self
+    }
+
+    /// Include when each [`Measurement`] was due to begin and actually began, relative to the epoch
+    /// of the run; in CSV output, this appends the [`ARRIVAL_COLUMNS`] to each row. JSON output
+    /// always includes them.
+    pub fn arrivals(self, with_arrivals: bool) -> Self {
+        self.with_columns(|c| c.arrivals = with_arrivals)
+    }
+
+    /// Accompany each [`Measurement`] with a [`ResidencyReport`]; in CSV output, this appends the
+    /// [`RESIDENCY_COLUMNS`] to each row (left empty for measurements without a report).
+    pub fn residency(self, with_residency: bool) -> Self {
+        self.with_columns(|c| c.residency = with_residency)
+    }
+
+    /// Accompany each [`Measurement`] with the [`RestoreLatencies`] reported by Firecracker; in
+    /// CSV output, this appends the [`FC_LATENCY_COLUMNS`] to each row (left empty for
+    /// measurements without them).
+    pub fn fc_latencies(self, with_fc_latencies: bool) -> Self {
+        self.with_columns(|c| c.fc_latencies = with_fc_latencies)
+    }
+
+    /// Include the [`ClockSync`] of each [`Measurement`] (see [`Measurement::clock`]); in CSV
+    /// output, this appends the [`CLOCK_COLUMNS`] to each row (left empty for measurements
+    /// without one). JSON output always includes it, if present.
+    pub fn sync_clock(self, with_clock: bool) -> Self {
+        self.with_columns(|c| c.clock = with_clock)
+    }
+
+    fn with_columns(mut self, f: impl FnOnce(&mut MeasurementColumns)) -> Self {
+        f(&mut self.columns);
+        self.rows.header = self.columns.header();
+        self
+    }
+
+    /// Write a single [`Measurement`]; `id` is ignored unless the writer was created `with_id`.
+    pub fn write(&mut self, id: usize, measurement: &Measurement) -> io::Result<()> {
+        self.write_record(id, measurement, None, None)
+    }
+
+    /// Write a single [`Measurement`], along with the [`ResidencyReport`] of the MicroVM's files;
+    /// the report is ignored unless the writer was configured to include it (see
+    /// [`MeasurementWriter::residency`]).
+    pub fn write_with_residency(
+        &mut self,
+        id: usize,
+        measurement: &Measurement,
+        residency: &ResidencyReport,
+    ) -> io::Result<()> {
+        self.write_record(id, measurement, Some(residency), None)
+    }
+
+    /// Write a single [`Measurement`], along with any [`ResidencyReport`] of the MicroVM's files
+    /// and any [`RestoreLatencies`] reported by Firecracker; either is ignored unless the writer
+    /// was configured to include it.
+    pub fn write_with_reports(
+        &mut self,
+        id: usize,
+        measurement: &Measurement,
+        residency: Option<&ResidencyReport>,
+        fc_latencies: Option<&RestoreLatencies>,
+    ) -> io::Result<()> {
+        self.write_record(id, measurement, residency, fc_latencies)
+    }
+
+    fn write_record(
+        &mut self,
+        id: usize,
+        measurement: &Measurement,
+        residency: Option<&ResidencyReport>,
+        fc_latencies: Option<&RestoreLatencies>,
+    ) -> io::Result<()> {
+        let columns = self.columns;
+        self.rows.write(&Record {
+            id: columns.id.then_some(id),
+            measurement,
+            residency: residency.filter(|_| columns.residency),
+            firecracker: fc_latencies.filter(|_| columns.fc_latencies),
+            columns,
+        })
+    }
+
+    /// Complete the output and flush the underlying writer.
+    pub fn finish(self) -> io::Result<()> {
+        self.rows.finish()
+    }
+}
+
+/// The MicroVM a [`LoadSummary`] refers to; `"all"` for the aggregate of all of them.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum LoadId {
+    Uvm(usize),
+    All(&'static str),
+}
+
+impl fmt::Display for LoadId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uvm(id) => write!(f, "{id}"),
+            Self::All(all) => f.write_str(all),
+        }
+    }
+}
+
+/// A single record of the output of [`LoadWriter`].
+#[derive(Serialize)]
+struct LoadRecord<'a> {
+    id: LoadId,
+    #[serde(flatten)]
+    summary: &'a LoadSummary,
+}
+
+impl Row for LoadRecord<'_> {
+    fn write_csv(&self, w: &mut impl Write, unit: TimeUnit) -> io::Result<()> {
+        let s = self.summary;
+        write!(w, "{},{},{},", self.id, s.requests, s.errors)?;
+        write!(w, "{}", DisplayDuration { d: s.elapsed, unit })?;
+        write!(w, ",{:.3}", s.throughput)?;
+        for d in [s.p50, s.p90, s.p99, s.p99_9, s.max] {
+            write!(w, ",{}", DisplayDuration { d, unit })?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes [`LoadSummary`]s (one per MicroVM, plus their aggregate) to the underlying writer, in
+/// the requested [`OutputFormat`].
+///
+/// [`LoadWriter::finish`] must be called after all summaries have been written, for the output to
+/// be complete (e.g., to close the JSON array).
+pub struct LoadWriter<W: Write> {
+    rows: RowWriter<W>,
+}
+
+impl<W: Write> LoadWriter<W> {
+    pub fn new(w: W, format: OutputFormat) -> Self {
+        Self {
+            rows: RowWriter::new(w, format, format!("id,{}", LOAD_COLUMNS.join(","))),
+        }
+    }
+
+    /// Set the [`TimeUnit`] for CSV output (it is ignored by the JSON formats).
+    pub fn time_unit(mut self, unit: TimeUnit) -> Self {
+        self.rows.unit = unit;
+        self
+    }
+
+    /// Write the [`LoadSummary`] of MicroVM `id`.
+    pub fn write(&mut self, id: usize, summary: &LoadSummary) -> io::Result<()> {
+        self.rows.write(&LoadRecord {
+            id: LoadId::Uvm(id),
+            summary,
+        })
+    }
+
+    /// Write the [`LoadSummary`] of all MicroVMs together (with `all` in place of the ID).
+    pub fn write_aggregate(&mut self, summary: &LoadSummary) -> io::Result<()> {
+        self.rows.write(&LoadRecord {
+            id: LoadId::All("all"),
+            summary,
+        })
+    }
+
+    /// Complete the output and flush the underlying writer.
+    pub fn finish(self) -> io::Result<()> {
+        self.rows.finish()
+    }
+}
+
+/// An [`InvocationRecord`] is written as a CSV row with the ID, the start and the error left empty
+/// if missing.
+impl Row for InvocationRecord {
+    fn write_csv(&self, w: &mut impl Write, unit: TimeUnit) -> io::Result<()> {
+        write!(w, "{},", self.index)?;
+        write!(w, "{}", DisplayDuration { d: self.at, unit })?;
+        write!(w, ",{},", self.function)?;
+        if let Some(id) = self.id {
+            write!(w, "{id}")?;
+        }
+        write!(w, ",{},", self.start.map_or("", |start| start.name()))?;
+        for d in [
+            self.wait,
+            self.latency,
+            self.restore,
+            self.resume,
+            self.connect,
+            self.client,
+            self.server,
+        ] {
+            write!(w, "{},", DisplayDuration { d, unit })?;
+        }
+        w.write_all(self.error.unwrap_or_default().as_bytes())
+    }
+}
+
+/// Writes [`InvocationRecord`]s to the underlying writer, in the requested [`OutputFormat`].
+///
+/// [`InvocationWriter::finish`] must be called after all records have been written, for the
+/// output to be complete (e.g., to close the JSON array).
+pub struct InvocationWriter<W: Write> {
+    rows: RowWriter<W>,
+}
+
+impl<W: Write> InvocationWriter<W> {
+    pub fn new(w: W, format: OutputFormat) -> Self {
+        Self {
+            rows: RowWriter::new(w, format, INVOCATION_COLUMNS.join(",")),
+        }
+    }
+
+    /// Set the [`TimeUnit`] for CSV output (it is ignored by the JSON formats).
+    pub fn time_unit(mut self, unit: TimeUnit) -> Self {
+        self.rows.unit = unit;
+        self
+    }
+
+    /// Write a single [`InvocationRecord`].
+    pub fn write(&mut self, record: &InvocationRecord) -> io::Result<()> {
+        self.rows.write(record)
+    }
+
+    /// Complete the output and flush the underlying writer.
+    pub fn finish(self) -> io::Result<()> {
+        self.rows.finish()
+    }
+}
+
+/// A single record of the output of [`TimelineWriter`].
+#[derive(Serialize)]
+struct TimelineRecord<'a> {
+    id: usize,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+impl Row for TimelineRecord<'_> {
+    fn write_csv(&self, w: &mut impl Write, unit: TimeUnit) -> io::Result<()> {
+        let Event { phase, start, end } = *self.event;
+        write!(w, "{},{},", self.id, phase.name())?;
+        write!(w, "{},", DisplayDuration { d: start, unit })?;
+        write!(w, "{}", DisplayDuration { d: end, unit })
+    }
+}
+
+/// Writes the [`Timeline`]s of MicroVMs to the underlying writer, one [`Event`] per record, in the
+/// requested [`OutputFormat`].
+///
+/// [`TimelineWriter::finish`] must be called after all timelines have been written, for the output
+/// to be complete (e.g., to close the JSON array).
+pub struct TimelineWriter<W: Write> {
+    rows: RowWriter<W>,
+}
+
+impl<W: Write> TimelineWriter<W> {
+    pub fn new(w: W, format: OutputFormat) -> Self {
+        Self {
+            rows: RowWriter::new(w, format, TIMELINE_COLUMNS.join(",")),
+        }
+    }
+
+    /// Set the [`TimeUnit`] for CSV output (it is ignored by the JSON formats).
+    pub fn time_unit(mut self, unit: TimeUnit) -> Self {
+        self.rows.unit = unit;
+        self
+    }
+
+    /// Write all [`Event`]s of the [`Timeline`] of MicroVM `id`.
+    pub fn write(&mut self, id: usize, timeline: &Timeline) -> io::Result<()> {
+        for event in timeline.events() {
+            self.rows.write(&TimelineRecord { id, event })?;
+        }
+        Ok(())
+    }
+
+    /// Complete the output and flush the underlying writer.
+    pub fn finish(self) -> io::Result<()> {
+        self.rows.finish()
+    }
+}
+
+/// A single event of the Chrome trace-event format; see [`TraceWriter`].
+#[derive(Serialize)]
+struct TraceEvent<'a> {
+    name: &'a str,
+    ph: &'static str,
+    /// In (fractional) microseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ts: Option<f64>,
+    /// In (fractional) microseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<f64>,
+    pid: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tid: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    args: Option<serde_json::Value>,
+}
+
+/// The "process" that all tracks of a [`TraceWriter`] belong to.
+const TRACE_PID: u32 = 1;
+
+fn trace_micros(d: Duration) -> f64 {
+    d.as_nanos() as f64 / 1e3
+}
+
+/// Writes the [`Timeline`]s of MicroVMs to the underlying writer in the Chrome trace-event format
+/// (as understood by Perfetto and `chrome://tracing`), with one track per MicroVM and one span per
+/// [`Event`], along with the host's memory usage as counter tracks.
+///
+/// [`TraceWriter::finish`] must be called after all timelines and samples have been written, for
+/// the output to be complete.
+pub struct TraceWriter<W: Write> {
+    w: W,
+    written: usize,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(w: W) -> Self {
+        Self { w, written: 0 }
+    }
+
+    /// Write all [`Event`]s of the [`Timeline`] of MicroVM `id`, on a track of its own.
+    pub fn write(&mut self, id: usize, timeline: &Timeline) -> io::Result<()> {
+        let name = format!("uVM {id}");
+        self.write_event(&TraceEvent {
+            name: "thread_name",
+            ph: "M",
+            ts: None,
+            dur: None,
+            pid: TRACE_PID,
+            tid: Some(id),
+            args: Some(serde_json::json!({ "name": name })),
+        })?;
+        self.write_event(&TraceEvent {
+            name: "thread_sort_index",
+            ph: "M",
+            ts: None,
+            dur: None,
+            pid: TRACE_PID,
+            tid: Some(id),
+            args: Some(serde_json::json!({ "sort_index": id })),
+        })?;
+        for event in timeline.events() {
+            self.write_event(&TraceEvent {
+                name: event.phase.name(),
+                ph: "X",
+                ts: Some(trace_micros(event.start)),
+                dur: Some(trace_micros(event.end.saturating_sub(event.start))),
+                pid: TRACE_PID,
+                tid: Some(id),
+                args: None,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Write the host's memory usage (in MiB) as counter tracks.
+    pub fn write_memory(&mut self, samples: &[MemorySample]) -> io::Result<()> {
+        const MIB: f64 = (1 << 20) as f64;
+        for sample in samples {
+            self.write_event(&TraceEvent {
+                name: "host memory (MiB)",
+                ph: "C",
+                ts: Some(trace_micros(sample.at)),
+                dur: None,
+                pid: TRACE_PID,
+                tid: None,
+                args: Some(serde_json::json!({
+                    "used": sample.used as f64 / MIB,
+                    "cached": sample.cached as f64 / MIB,
+                })),
+            })?;
+        }
+        Ok(())
+    }
+
+    fn write_event(&mut self, event: &TraceEvent<'_>) -> io::Result<()> {
+        if self.written == 0 {
+            self.begin()?;
+        } else {
+            self.w.write_all(b",")?;
+        }
+        self.w.write_all(b"\n")?;
+        serde_json::to_writer(&mut self.w, event)?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Complete the output and flush the underlying writer.
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.written == 0 {
+            self.begin()?;
+        }
+        self.w.write_all(b"\n],\"displayTimeUnit\":\"ms\"}\n")?;
+        self.w.flush()
+    }
+
+    fn begin(&mut self) -> io::Result<()> {
+        self.w.write_all(b"{\"traceEvents\":[")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_time_units() {
+        for unit in [
+            TimeUnit::Nanos,
+            TimeUnit::Micros,
+            TimeUnit::Millis,
+            TimeUnit::FractionalSecs,
+        ] {
+            assert_eq!(unit.to_string().parse::<TimeUnit>(), Ok(unit));
+        }
+        assert_eq!("µs".parse::<TimeUnit>(), Ok(TimeUnit::Micros));
+        for s in ["", "sec", "US", "m"] {
+            assert!(s.parse::<TimeUnit>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn writes_durations_in_each_unit() {
+        let d = Duration::new(3, 4_005_006);
+        let written = |unit: TimeUnit| {
+            let mut s = String::new();
+            unit.write_duration(&mut s, d).unwrap();
+            s
+        };
+        assert_eq!(written(TimeUnit::Nanos), "3004005006");
+        assert_eq!(written(TimeUnit::Micros), "3004005");
+        assert_eq!(written(TimeUnit::Millis), "3004");
+        assert_eq!(written(TimeUnit::FractionalSecs), "3.004005006");
+    }
+}
@@ -0,0 +1,508 @@
+//! Replaying invocation traces (in the spirit of the Azure Functions traces) against a pool of
+//! MicroVMs, which are restored from their snapshots on demand.
+//!
+//! Each invocation of a trace is dispatched, at its timestamp, to an idle MicroVM that already
+//! serves its function, if any (i.e., a *warm* start). Otherwise, a MicroVM is restored from the
+//! function's snapshot (i.e., a *cold* start): either a stopped one, or the least recently used
+//! idle one, which is terminated first to make room; if all of them are busy, the invocation is
+//! queued. MicroVMs that stay idle for longer than the keep-alive window are terminated, so that
+//! the next invocation they would serve is cold.
+//!
+//! A trace is a CSV file with one invocation per line, as `timestamp,function,args`, where:
+//!
+//! - `timestamp` is the (fractional) number of seconds since the beginning of the trace;
+//! - `function` is the name of a [`Benchmark`];
+//! - `args` are the benchmark's arguments, separated by whitespace (or nothing, for its defaults).
+//!
+//! Empty lines, lines starting with `#` and a header line (e.g., `timestamp,function,args`) are
+//! ignored, and invocations need not be sorted by their timestamps:
+//!
+//! ```text
+//! timestamp,function,args
+//! 0.0,chameleon,
+//! 0.25,matmul_fb,512 512
+//! 1.5,chameleon,100 100
+//! ```
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs, io, mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde::Serialize;
+use tokio::{
+    task::JoinHandle,
+    time::{sleep_until, Instant},
+};
+
+use crate::{
+    bench::{BenchError, Benchmark},
+    firecracker::SnapshotLoadParams,
+    vmm::Vmm,
+    BenchClient, Delays, Error, Result, RpcArgs,
+};
+
+/// Errors that may occur while reading a trace.
+#[derive(Debug, thiserror::Error)]
+pub enum TraceError {
+    /// The trace could not be read.
+    #[error("failed to read trace '{}': {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// A line of the trace is malformed.
+    #[error("{}:{line}: {reason}", .path.display())]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+
+    /// A line of the trace refers to an unknown benchmark, or to invalid arguments for it.
+    #[error("{}:{line}: {source}", .path.display())]
+    Bench {
+        path: PathBuf,
+        line: usize,
+        #[source]
+        source: BenchError,
+    },
+}
+
+/// A single invocation of a trace.
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    /// When the invocation is due, since the beginning of the trace.
+    pub at: Duration,
+    /// The function (i.e., benchmark) to invoke.
+    pub bench: &'static Benchmark,
+    /// The arguments of the invocation.
+    pub args: RpcArgs,
+}
+
+/// An invocation trace, sorted by the invocations' timestamps.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    invocations: Vec<Invocation>,
+}
+
+impl Trace {
+    /// Read and parse the trace in `path`.
+    pub fn from_file(path: &Path) -> Result<Self, TraceError> {
+        let text = fs::read_to_string(path).map_err(|source| TraceError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        let mut invocations = Vec::new();
+        let mut first = true;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parse_error = |reason: String| TraceError::Parse {
+                path: path.to_path_buf(),
+                line: i + 1,
+                reason,
+            };
+
+            let fields: Vec<_> = line.split(',').map(str::trim).collect();
+            let (at, function, args) = match fields[..] {
+                [at, function] => (at, function, ""),
+                [at, function, args] => (at, function, args),
+                _ => {
+                    return Err(parse_error(format!(
+                        "expected 'timestamp,function,args'; found {} fields",
+                        fields.len()
+                    )))
+                }
+            };
+            let at = match at.parse::<f64>() {
+                Ok(at) => at,
+                // Skip the header, if any
+                Err(_) if first => {
+                    first = false;
+                    continue;
+                }
+                Err(err) => return Err(parse_error(format!("invalid timestamp '{at}': {err}"))),
+            };
+            first = false;
+            let at = Duration::try_from_secs_f64(at)
+                .map_err(|err| parse_error(format!("invalid timestamp '{at}': {err}")))?;
+
+            let bench_error = |source| TraceError::Bench {
+                path: path.to_path_buf(),
+                line: i + 1,
+                source,
+            };
+            let bench = Benchmark::lookup(function).map_err(bench_error)?;
+            let args = match args.split_whitespace().collect::<Vec<_>>() {
+                args if args.is_empty() => bench.default_rpc_args(),
+                args => bench.rpc_args(&args).map_err(bench_error)?,
+            };
+            invocations.push(Invocation { at, bench, args });
+        }
+        invocations.sort_by_key(|invocation| invocation.at);
+
+        Ok(Self { invocations })
+    }
+
+    /// The invocations of the trace, in the order they are due.
+    pub fn invocations(&self) -> &[Invocation] {
+        &self.invocations
+    }
+
+    /// The distinct functions (i.e., benchmarks) that are invoked throughout the trace.
+    pub fn benchmarks(&self) -> Vec<&'static Benchmark> {
+        let mut benches: Vec<&'static Benchmark> = Vec::new();
+        for invocation in &self.invocations {
+            if !benches.contains(&invocation.bench) {
+                benches.push(invocation.bench);
+            }
+        }
+        benches
+    }
+}
+
+/// A MicroVM of the pool that serves the invocations of a trace.
+#[derive(Debug, Clone)]
+pub struct ReplayUvm {
+    /// The ID of the MicroVM.
+    pub id: usize,
+    /// The address of its gRPC server (e.g., `http://10.0.0.2:50051`).
+    pub addr: String,
+    /// The path of the API socket of its Firecracker process.
+    pub api_sock: PathBuf,
+    /// The snapshot to restore it from, for each function (i.e., benchmark name) it may serve.
+    pub snapshots: HashMap<&'static str, SnapshotLoadParams>,
+}
+
+/// The options of a replay.
+#[derive(Debug, Clone)]
+pub struct ReplayOpts {
+    /// Path to the Firecracker binary.
+    pub fc_bin: PathBuf,
+    /// For how long an idle MicroVM is kept alive, before being terminated.
+    pub keep_alive: Duration,
+}
+
+/// Whether an invocation was served by a MicroVM that had to be restored from its snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Start {
+    Cold,
+    Warm,
+}
+
+impl Start {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Cold => "cold",
+            Self::Warm => "warm",
+        }
+    }
+}
+
+/// The outcome of a single invocation of a trace.
+#[derive(Debug, Clone, Serialize)]
+pub struct InvocationRecord {
+    /// The position of the invocation in the (sorted) trace.
+    pub index: usize,
+    /// When the invocation was due, since the beginning of the trace.
+    pub at: Duration,
+    /// The function (i.e., benchmark) invoked.
+    pub function: &'static str,
+    /// The MicroVM that served the invocation, unless none of them could.
+    pub id: Option<usize>,
+    /// Whether the invocation was a cold or a warm start, unless no MicroVM could serve it.
+    pub start: Option<Start>,
+    /// The time the invocation was queued for, until a MicroVM became available to serve it.
+    pub wait: Duration,
+    /// The time from when the invocation was due until its response arrived; for cold starts, this
+    /// includes terminating the evicted MicroVM (if any) and spawning, restoring and resuming the
+    /// new one.
+    pub latency: Duration,
+    /// The delay for restoring the MicroVM from its snapshot (cold starts only).
+    pub restore: Duration,
+    /// The delay for resuming the MicroVM (cold starts only).
+    pub resume: Duration,
+    /// The delay for connecting to the MicroVM's gRPC server (cold starts only).
+    pub connect: Duration,
+    /// The delay of the request, as measured by the client.
+    pub client: Duration,
+    /// The delay of the request, as measured by the server.
+    pub server: Duration,
+    /// The kind of the error (see [`Error::kind`]) if the invocation failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'static str>,
+    /// The complete error message, if the invocation failed.
+    #[serde(skip)]
+    pub message: Option<String>,
+}
+
+impl InvocationRecord {
+    fn new(index: usize, invocation: &Invocation) -> Self {
+        Self {
+            index,
+            at: invocation.at,
+            function: invocation.bench.name,
+            id: None,
+            start: None,
+            wait: Duration::ZERO,
+            latency: Duration::ZERO,
+            restore: Duration::ZERO,
+            resume: Duration::ZERO,
+            connect: Duration::ZERO,
+            client: Duration::ZERO,
+            server: Duration::ZERO,
+            error: None,
+            message: None,
+        }
+    }
+
+    fn fail(&mut self, err: &Error) {
+        self.error = Some(err.kind());
+        self.message = Some(err.to_string());
+    }
+}
+
+/// A MicroVM that has been restored and connected to.
+struct Running {
+    bench: &'static Benchmark,
+    vmm: Vmm,
+    client: BenchClient,
+}
+
+/// The state of each MicroVM of the pool.
+enum State {
+    Stopped,
+    Idle { uvm: Box<Running>, since: Instant },
+    Busy,
+}
+
+/// The outcome of an invocation task: the slot of the MicroVM it was assigned to, the MicroVM (if
+/// it is still alive) and the record of the invocation.
+type Outcome = (usize, Option<Running>, InvocationRecord);
+
+/// How an invocation is going to be served by the MicroVM it was assigned to.
+enum Assignment {
+    /// By the MicroVM, as is.
+    Warm(Running),
+    /// By restoring the MicroVM, after terminating the one it currently runs (if any).
+    Cold(Option<Running>),
+}
+
+/// Replay `trace` on the given pool of MicroVMs, returning the records of all invocations in the
+/// order of the trace.
+///
+/// Invocations that arrive while all MicroVMs that could serve them are busy are queued (in
+/// order) until one of them becomes available. Failed invocations are reported through their
+/// records, rather than aborting the replay.
+pub async fn replay(
+    trace: &Trace,
+    uvms: Vec<ReplayUvm>,
+    opts: &ReplayOpts,
+) -> Result<Vec<InvocationRecord>> {
+    let uvms: Vec<_> = uvms.into_iter().map(Arc::new).collect();
+    let mut states: Vec<_> = uvms.iter().map(|_| State::Stopped).collect();
+    let mut records = Vec::with_capacity(trace.invocations.len());
+    let mut in_flight: FuturesUnordered<JoinHandle<Outcome>> = FuturesUnordered::new();
+    let mut terminating = Vec::new();
+
+    let invocations = &trace.invocations;
+    let mut arrivals = invocations.iter().enumerate().peekable();
+    let mut queued = VecDeque::new();
+    let epoch = Instant::now();
+    loop {
+        let next = arrivals.peek().map(|(_, invocation)| epoch + invocation.at);
+        tokio::select! {
+            _ = sleep_until(next.unwrap_or(epoch)), if next.is_some() => {
+                let (index, _) = arrivals.next().expect("an invocation is due");
+                queued.push_back(index);
+            }
+            Some(joined) = in_flight.next() => {
+                let (slot, uvm, record) = joined.expect("invocation task panicked");
+                states[slot] = match uvm {
+                    Some(uvm) => State::Idle { uvm: Box::new(uvm), since: Instant::now() },
+                    None => State::Stopped,
+                };
+                records.push(record);
+            }
+            else => break,
+        }
+
+        // Terminate all MicroVMs that have outlived their keep-alive window
+        let now = Instant::now();
+        for state in &mut states {
+            if matches!(state, State::Idle { since, .. } if now - *since > opts.keep_alive) {
+                if let State::Idle { uvm, .. } = mem::replace(state, State::Stopped) {
+                    terminating.push(tokio::spawn(uvm.vmm.terminate()));
+                }
+            }
+        }
+
+        // Dispatch the queued invocations, in order, to whichever MicroVMs can serve them
+        queued.retain(|&index| {
+            let invocation: &Invocation = &invocations[index];
+            match assign(&mut states, &uvms, invocation.bench) {
+                Some((slot, assignment)) => {
+                    in_flight.push(tokio::spawn(invoke(
+                        slot,
+                        uvms[slot].clone(),
+                        opts.fc_bin.clone(),
+                        invocation.clone(),
+                        epoch + invocation.at,
+                        assignment,
+                        InvocationRecord::new(index, invocation),
+                    )));
+                    false
+                }
+                None => true,
+            }
+        });
+    }
+
+    // Invocations that are still queued cannot be served by any MicroVM of the pool
+    for index in queued {
+        let invocation = &invocations[index];
+        let mut record = InvocationRecord::new(index, invocation);
+        record.error = Some("unservable");
+        record.message = Some(format!(
+            "no MicroVM can be restored for '{}'",
+            invocation.bench.name
+        ));
+        records.push(record);
+    }
+
+    // Terminate all MicroVMs that are still alive
+    for state in states {
+        if let State::Idle { uvm, .. } = state {
+            terminating.push(tokio::spawn(uvm.vmm.terminate()));
+        }
+    }
+    for handle in terminating {
+        handle.await.expect("termination task panicked")?;
+    }
+
+    records.sort_by_key(|record| record.index);
+    Ok(records)
+}
+
+/// Pick the MicroVM of the pool to serve an invocation of `bench`, marking it busy.
+fn assign(
+    states: &mut [State],
+    uvms: &[Arc<ReplayUvm>],
+    bench: &'static Benchmark,
+) -> Option<(usize, Assignment)> {
+    // An idle MicroVM that already serves the function
+    let warm = states
+        .iter()
+        .position(|state| matches!(state, State::Idle { uvm, .. } if uvm.bench == bench));
+    if let Some(slot) = warm {
+        return match mem::replace(&mut states[slot], State::Busy) {
+            State::Idle { uvm, .. } => Some((slot, Assignment::Warm(*uvm))),
+            _ => unreachable!(),
+        };
+    }
+
+    // Otherwise, a stopped MicroVM, or else the least recently used idle one
+    let can_serve = |slot: usize| uvms[slot].snapshots.contains_key(bench.name);
+    let stopped =
+        (0..states.len()).find(|&slot| matches!(states[slot], State::Stopped) && can_serve(slot));
+    let slot = stopped.or_else(|| {
+        (0..states.len())
+            .filter(|&slot| can_serve(slot))
+            .filter_map(|slot| match &states[slot] {
+                State::Idle { since, .. } => Some((slot, *since)),
+                _ => None,
+            })
+            .min_by_key(|&(_, since)| since)
+            .map(|(slot, _)| slot)
+    })?;
+    match mem::replace(&mut states[slot], State::Busy) {
+        State::Idle { uvm, .. } => Some((slot, Assignment::Cold(Some(*uvm)))),
+        _ => Some((slot, Assignment::Cold(None))),
+    }
+}
+
+/// Serve an invocation on the MicroVM of the given `slot`, returning it (unless the invocation
+/// failed, in which case it is killed) along with the record of the invocation.
+async fn invoke(
+    slot: usize,
+    uvm: Arc<ReplayUvm>,
+    fc_bin: PathBuf,
+    invocation: Invocation,
+    due: Instant,
+    assignment: Assignment,
+    mut record: InvocationRecord,
+) -> Outcome {
+    record.id = Some(uvm.id);
+    record.wait = Instant::now() - due;
+    let res = match assignment {
+        Assignment::Warm(running) => {
+            record.start = Some(Start::Warm);
+            warm_start(running, &invocation.args, &mut record).await
+        }
+        Assignment::Cold(evicted) => {
+            record.start = Some(Start::Cold);
+            cold_start(&uvm, &fc_bin, evicted, &invocation, &mut record).await
+        }
+    };
+    record.latency = Instant::now() - due;
+
+    match res {
+        Ok(running) => (slot, Some(running), record),
+        Err(err) => {
+            record.fail(&err);
+            (slot, None, record)
+        }
+    }
+}
+
+async fn warm_start(
+    running: Running,
+    args: &RpcArgs,
+    record: &mut InvocationRecord,
+) -> Result<Running> {
+    let delays: Delays = running.client.bench(args).await?.try_into()?;
+    record.client = delays.client();
+    record.server = delays.server();
+    Ok(running)
+}
+
+async fn cold_start(
+    uvm: &ReplayUvm,
+    fc_bin: &Path,
+    evicted: Option<Running>,
+    invocation: &Invocation,
+    record: &mut InvocationRecord,
+) -> Result<Running> {
+    if let Some(evicted) = evicted {
+        evicted.vmm.terminate().await?;
+    }
+    let bench = invocation.bench;
+    let vm_id = format!("{}-{:02X}", bench.cli_name(), uvm.id);
+    let mut vmm = Vmm::spawn(fc_bin, &vm_id, uvm.api_sock.clone(), None).await?;
+    let api = vmm.wait_api().await?;
+
+    record.restore = api
+        .load_snapshot(&uvm.snapshots[bench.name])
+        .await
+        .map_err(Error::SnapshotLoad)?;
+    record.resume = api.resume().await.map_err(Error::Resume)?;
+
+    let mut client = BenchClient::new(uvm.addr.clone())?;
+    record.connect = client.connect().await?;
+    let delays: Delays = client.bench(&invocation.args).await?.try_into()?;
+    record.client = delays.client();
+    record.server = delays.server();
+
+    Ok(Running { bench, vmm, client })
+}
@@ -0,0 +1,187 @@
+//! Absolute timelines of the phases that each MicroVM goes through during a run.
+//!
+//! While a [`Measurement`](crate::Measurement) only retains the duration of each phase, a
+//! [`Timeline`] also records when each phase began and ended, relative to an [`Epoch`] shared by
+//! all MicroVMs of the run, so that any overlap among the phases of concurrent MicroVMs can be
+//! studied (e.g., in a Gantt chart).
+//!
+//! The host's memory usage may also be sampled throughout a run by a [`MemorySampler`], to be
+//! plotted alongside the timelines.
+
+use std::{
+    fs, io,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::{
+    sync::oneshot,
+    task::JoinHandle,
+    time::{self, Instant, MissedTickBehavior},
+};
+
+/// The instant a run began, shared by all of its worker tasks.
+///
+/// It is set by the first worker that asks for it (i.e., right after they all pass the first
+/// rendezvous), so that its allocation does not skew it.
+#[derive(Debug, Default, Clone)]
+pub struct Epoch(Arc<OnceLock<Instant>>);
+
+impl Epoch {
+    /// The instant the run began; set to the current instant, if this is the first call.
+    pub fn get(&self) -> Instant {
+        *self.0.get_or_init(Instant::now)
+    }
+}
+
+/// A phase of a MicroVM's lifetime during a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Loading the snapshot of the MicroVM.
+    Restore,
+    /// Resuming the MicroVM after it has been restored.
+    Resume,
+    /// Setting the guest's clock, right after it has been resumed.
+    SyncClock,
+    /// Establishing the connection to the gRPC server, right before the "cold" request.
+    Connect,
+    /// The "cold" request.
+    Cold,
+    /// A single pre-warm request.
+    PreWarm,
+    /// The "warm" request.
+    Warm,
+}
+
+impl Phase {
+    /// The name of the phase, as it appears in the output.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Restore => "restore",
+            Self::Resume => "resume",
+            Self::SyncClock => "sync_clock",
+            Self::Connect => "connect",
+            Self::Cold => "cold",
+            Self::PreWarm => "pre_warm",
+            Self::Warm => "warm",
+        }
+    }
+}
+
+/// A phase that a MicroVM went through, along with when it began and ended, relative to the
+/// [`Epoch`] of the run.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Event {
+    pub phase: Phase,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+/// The [`Event`]s of a single MicroVM, in the order they were recorded.
+#[derive(Debug, Default, Clone)]
+pub struct Timeline {
+    events: Vec<Event>,
+}
+
+impl Timeline {
+    /// Record that the given `phase` lasted from `start` until `end`; instants before the `epoch`
+    /// are recorded as the epoch itself.
+    pub fn record(&mut self, phase: Phase, epoch: Instant, start: Instant, end: Instant) {
+        self.events.push(Event {
+            phase,
+            start: start.saturating_duration_since(epoch),
+            end: end.saturating_duration_since(epoch),
+        });
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+/// The host's memory usage at some point during a run.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct MemorySample {
+    /// When the sample was taken, relative to the [`Epoch`] of the run.
+    pub at: Duration,
+    /// The memory in use, in bytes (i.e., `MemTotal - MemAvailable`, as per `/proc/meminfo`).
+    pub used: u64,
+    /// The memory used by the page cache, in bytes (i.e., `Cached`, as per `/proc/meminfo`).
+    pub cached: u64,
+}
+
+/// Samples the host's memory usage periodically, in the background, until it is stopped.
+#[derive(Debug)]
+pub struct MemorySampler {
+    epoch: Epoch,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<io::Result<Vec<(Instant, u64, u64)>>>,
+}
+
+impl MemorySampler {
+    /// Start sampling the host's memory usage every `interval`, beginning right away.
+    pub fn spawn(epoch: Epoch, interval: Duration) -> Self {
+        let (stop, mut stopped) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            let mut ticks = time::interval(interval);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            let mut samples = Vec::new();
+            loop {
+                tokio::select! {
+                    _ = ticks.tick() => {
+                        let (used, cached) = read_meminfo()?;
+                        samples.push((Instant::now(), used, cached));
+                    }
+                    _ = &mut stopped => break,
+                }
+            }
+            Ok(samples)
+        });
+        Self { epoch, stop, task }
+    }
+
+    /// Stop sampling and return all samples taken; those taken before the [`Epoch`] of the run
+    /// are reported as taken at the epoch itself.
+    pub async fn stop(self) -> io::Result<Vec<MemorySample>> {
+        let _ = self.stop.send(());
+        let samples = self.task.await.map_err(io::Error::other)??;
+        let epoch = self.epoch.get();
+        Ok(samples
+            .into_iter()
+            .map(|(at, used, cached)| MemorySample {
+                at: at.saturating_duration_since(epoch),
+                used,
+                cached,
+            })
+            .collect())
+    }
+}
+
+/// Read the memory in use and the memory used by the page cache (in bytes) from `/proc/meminfo`.
+fn read_meminfo() -> io::Result<(u64, u64)> {
+    const MEMINFO: &str = "/proc/meminfo";
+    let meminfo = fs::read_to_string(MEMINFO)?;
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|value| value.trim().strip_suffix(" kB")?.parse::<u64>().ok())
+            .map(|kib| kib << 10)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("missing or malformed '{name}' in {MEMINFO}"),
+                )
+            })
+    };
+    Ok((
+        field("MemTotal")?.saturating_sub(field("MemAvailable")?),
+        field("Cached")?,
+    ))
+}
@@ -0,0 +1,173 @@
+//! Firecracker processes, managed as children of the calling process.
+
+use std::{
+    io, mem,
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    time::Duration,
+};
+
+use tokio::{
+    fs,
+    process::{Child, Command},
+    time::{sleep, timeout, Instant},
+};
+
+use crate::FirecrackerApi;
+
+/// How long to wait for a Firecracker process to create its API socket.
+const API_SOCK_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait for a Firecracker process to exit after `SIGTERM`, before killing it.
+const TERM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Errors that may occur while managing a Firecracker process.
+#[derive(Debug, thiserror::Error)]
+pub enum VmmError {
+    /// The Firecracker binary could not be spawned.
+    #[error("failed to spawn '{}': {source}", .fc_bin.display())]
+    Spawn {
+        fc_bin: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// Firecracker exited before creating its API socket.
+    #[error("Firecracker exited prematurely ({0})")]
+    Exited(ExitStatus),
+
+    /// Firecracker did not create its API socket in time.
+    #[error("Firecracker did not create '{}' within {API_SOCK_TIMEOUT:?}", .sock.display())]
+    ApiTimeout { sock: PathBuf },
+
+    /// A system call on the process or on its API socket failed.
+    #[error("failed to {op} '{}': {source}", .path.display())]
+    Io {
+        op: &'static str,
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+pub type Result<T, E = VmmError> = std::result::Result<T, E>;
+
+/// A (not yet configured) Firecracker process, waiting for a snapshot to be loaded through its
+/// API socket.
+///
+/// The process is killed when the `Vmm` is dropped, unless it has already been terminated through
+/// [`Vmm::terminate`].
+#[derive(Debug)]
+pub struct Vmm {
+    sock: PathBuf,
+    child: Child,
+}
+
+impl Vmm {
+    /// Spawn a Firecracker process with the given `--id`, serving its API at `sock` (which is
+    /// unlinked first, if it already exists), and possibly pinning all its threads on `core`.
+    pub async fn spawn(
+        fc_bin: &Path,
+        vm_id: &str,
+        sock: PathBuf,
+        core: Option<usize>,
+    ) -> Result<Self> {
+        if fs::metadata(&sock).await.is_ok() {
+            fs::remove_file(&sock)
+                .await
+                .map_err(io_error("remove old API socket", &sock))?;
+        }
+
+        let mut cmd = Command::new(fc_bin);
+        cmd.arg("--id")
+            .arg(vm_id)
+            .arg("--api-sock")
+            .arg(&sock)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .kill_on_drop(true);
+        if let Some(core) = core {
+            // SAFETY: `set_affinity` is safe to call between fork(2) and exec(2).
+            unsafe {
+                cmd.pre_exec(move || set_affinity(&[core]));
+            }
+        }
+        let child = cmd.spawn().map_err(|source| VmmError::Spawn {
+            fc_bin: fc_bin.to_path_buf(),
+            source,
+        })?;
+
+        Ok(Self { sock, child })
+    }
+
+    /// The path of the API socket of the Firecracker process.
+    pub fn sock(&self) -> &Path {
+        &self.sock
+    }
+
+    /// Wait until the API socket of the Firecracker process shows up.
+    pub async fn wait_api(&mut self) -> Result<FirecrackerApi> {
+        let start = Instant::now();
+        while fs::metadata(&self.sock).await.is_err() {
+            if let Some(status) = self
+                .child
+                .try_wait()
+                .map_err(io_error("wait for", &self.sock))?
+            {
+                return Err(VmmError::Exited(status));
+            }
+            if start.elapsed() > API_SOCK_TIMEOUT {
+                return Err(VmmError::ApiTimeout {
+                    sock: self.sock.clone(),
+                });
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        Ok(FirecrackerApi::new(&self.sock))
+    }
+
+    /// Terminate the Firecracker process (`SIGTERM`, falling back to `SIGKILL`), wait for it to
+    /// exit, and remove its API socket.
+    pub async fn terminate(mut self) -> Result<()> {
+        if let Some(pid) = self.child.id() {
+            // SAFETY: `pid` refers to our own child, which has not been reaped yet.
+            unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+            if timeout(TERM_TIMEOUT, self.child.wait()).await.is_err() {
+                self.child
+                    .kill()
+                    .await
+                    .map_err(io_error("kill the Firecracker process behind", &self.sock))?;
+            }
+        }
+        if fs::metadata(&self.sock).await.is_ok() {
+            fs::remove_file(&self.sock)
+                .await
+                .map_err(io_error("remove API socket", &self.sock))?;
+        }
+        Ok(())
+    }
+}
+
+fn io_error<'a>(op: &'static str, path: &'a Path) -> impl FnOnce(io::Error) -> VmmError + 'a {
+    move |source| VmmError::Io {
+        op,
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// Pin the calling thread, along with any threads or processes it creates afterwards, to `cpus`.
+///
+/// It neither allocates nor locks, so that it is safe to call between `fork(2)` and `exec(2)`.
+pub fn set_affinity(cpus: &[usize]) -> io::Result<()> {
+    // SAFETY: `cpu_set_t` is a plain bitmask, for which all zeroes is a valid (empty) value.
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    for &cpu in cpus {
+        // SAFETY: `CPU_SET` panics (rather than writing out of bounds) for CPUs beyond the set.
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    // SAFETY: `set` is a properly initialized `cpu_set_t` of the given size.
+    match unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
@@ -0,0 +1,31 @@
+import time
+
+from google.protobuf.timestamp_pb2 import Timestamp
+import grpc
+
+import functionbench_pmem_local_pb2 as fbpml
+import functionbench_pmem_local_pb2_grpc as fbpml_grpc
+
+
+class Clock(fbpml_grpc.ClockServicer):
+    def SetTime(
+        self,
+        request: fbpml.SetTimeRequest,
+        context: grpc.ServicerContext,
+    ):
+        previous = Timestamp()
+        previous.GetCurrentTime()
+        try:
+            time.clock_settime_ns(
+                time.CLOCK_REALTIME, request.time.ToNanoseconds()
+            )
+        except OSError as err:
+            context.abort(
+                grpc.StatusCode.PERMISSION_DENIED,
+                f"failed to set the clock: {err}",
+            )
+        return fbpml.SetTimeResponse(previous=previous)
+
+
+def add_to_server(server: grpc.Server):
+    fbpml_grpc.add_ClockServicer_to_server(Clock(), server)
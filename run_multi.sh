@@ -225,24 +225,8 @@ physical_cores "$FC_NN"  # Populate the global $PHYS_CORES array
 # The root directory of all rootfs images
 ROOTFS_PATH="$SCRIPT_DIR/rootfs"
 
-# Benchmarks' input arguments.
-declare -A INPUTS=(
-	['chameleon']='10 15'
-	['cnn_serving']='0'
-	['helloworld']=''
-	['image_rotate']='2'
-	['json_serdes']='0'
-	['matmul_fb']='512 512'
-	['matmul_fbpml']=''
-	['lr_serving']='0'
-	['lr_training']='0'
-	['pyaes']=''
-	['rnn_serving']='0'
-	['video_processing']='0'
-)
-
-# Parse $BENCH's input arguments into the $ARGS array
-IFS=' ' read -r -a ARGS <<< "${INPUTS["$BENCH"]}"
+# NOTE: Benchmarks' input arguments are omitted below, so that the defaults of
+# the benchmark catalog in fbpml (see `fbpml-rs/fbpml/src/bench.rs`) are used.
 
 
 # Commonly-indexed arrays of devices and their final snapshot paths, based on
@@ -371,7 +355,6 @@ for d in "${!DEVICES[@]}"; do
 					--state-file "$state_file_fmt" \
 					--memory-file "$memory_file_fmt" \
 					"${BENCH//_/-}" \
-						"${ARGS[@]}" \
 				>>"$outfile"
 
 		# Wait for all MicroVMs to terminate so that all API sockets and tap
@@ -2,39 +2,44 @@ use std::{
     path::{Path, PathBuf},
     process::Stdio,
     sync::Arc,
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::Parser;
+use fbpml::{
+    bench::Benchmark,
+    diff,
+    firecracker::{
+        BootSource, Drive, LogLevel, Logger, MachineConfig, Metrics, NetworkInterface,
+        SnapshotCreateParams, SnapshotType, VmConfig,
+    },
+    manifest::{FileEntry, Manifest, ManifestError, SnapshotEntry, MINIO_MAX_SKEW},
+    BenchClient, FirecrackerApi, RpcArgs,
+};
 use futures::future;
-use hyper::{Client, Request, StatusCode};
-use hyperlocal::{UnixClientExt, UnixConnector, Uri};
 use indicatif::{ProgressBar, ProgressStyle};
 use rand::{prelude::StdRng, Rng, SeedableRng};
 use tokio::{fs, net::TcpStream, process::Command, time::sleep};
 
 const VM_ADDR_FMT: &str = "10.0.ID.2:50051";
 
-const ACCEPT: &str = "Accept";
-const CONTENT_TYPE: &str = "Content-Type";
-const APPLICATION_JSON: &str = "application/json";
-
 #[derive(Clone, Parser)]
 #[clap(author, version, about, long_about = None)]
 #[clap(propagate_version = true)]
 struct Cmd {
     /// The name of the benchmark to create snapshots for.
-    #[clap(short = 'b', long = "bench")]
-    bench: String,
+    #[clap(short = 'b', long = "bench", parse(try_from_str = Benchmark::lookup))]
+    bench: &'static Benchmark,
 
     /// Number of uVMs to create snapshots for.
     #[clap(short = 'n', long = "num-uvms")]
     num_uvms: u64,
 
-    /// Guest memory size for the uVMs to be snapshotted.
+    /// Guest memory size (in MiB) for the uVMs to be snapshotted; defaults to the one suggested
+    /// for the benchmark.
     #[clap(short = 'm', long = "vm-mem")]
-    vm_mem: u64,
+    vm_mem: Option<u64>,
 
     /// Number of VCPUs of the guest uVMs to be snapshotted.
     #[clap(long = "vcpu-count", default_value = "1")]
@@ -44,6 +49,25 @@ struct Cmd {
     #[clap(short = 'p', long = "store")]
     store_path: PathBuf,
 
+    /// Number of invocations of the benchmark to issue to each uVM before its full snapshot is
+    /// created, so that the snapshot captures a warmed working set (e.g., a loaded interpreter and
+    /// model weights) rather than an un-invoked function.
+    #[clap(long = "warm-invocations", default_value = "0")]
+    warm_invocations: usize,
+
+    /// The arguments of all warm-up invocations of the benchmark (i.e., those of
+    /// `--warm-invocations` and `--diff-snapshots`, either of which it requires); defaults to its
+    /// default ones.
+    #[clap(long = "bench-args", multiple_values = true)]
+    bench_args: Vec<String>,
+
+    /// Number of diff snapshots to create for each uVM after its full snapshot, each one following
+    /// a single warm-up invocation of the benchmark. The K-th one is stored
+    /// under `$STORE/diffK/`, along with a memory file that can be restored from right away (see
+    /// `fbpml::diff`).
+    #[clap(long = "diff-snapshots", default_value = "0")]
+    diff_snapshots: usize,
+
     /// Cleanup (configs, logs, metrics) after creating the snapshots.
     #[clap(short = 'r', long = "cleanup")]
     cleanup: bool,
@@ -132,170 +156,170 @@ async fn truncate_files(
     Ok((logs, metrics))
 }
 
-async fn render_config(id: u64, args: &Cmd) -> Result<PathBuf> {
-    let (logs, metrics) = truncate_files(id, &args.bench, &args.store_path)
-        .await
-        .with_context(|| "ID={id} failed to truncate logs & metrics files")?;
-
+/// The path of the rootfs image of uVM `id`.
+fn rootfs_path(id: u64, args: &Cmd) -> PathBuf {
     let mut rootfs = PathBuf::from(args.rootfs_dir.as_path());
-    rootfs.push(args.bench.as_str());
-    rootfs.push(format!("{}-{id:02X}.ext4", args.bench));
-
-    // The following probably does way too many allocations, but nvm cuz we're fast enough anyway.
-    // Crate `tinytemplate` might be a better alternative, although I have not really evaluated it.
-    let config = MICROVM_CONFIG_TEMPLATE
-        .replace(
-            "{KERNEL_IMG_PATH}",
-            args.kernel_image_path
-                .to_str()
-                .expect("non-utf8 kernel image path"),
-        )
-        .replace(
-            "{ROOTFS_PATH_ON_HOST}",
-            rootfs.to_str().expect("non-utf8 rootfs path"),
-        )
-        .replace("{VM_MEM_MB}", args.vm_mem.to_string().as_str())
-        .replace("{VCPU_COUNT}", args.vcpu_count.to_string().as_str())
-        .replace("{LOG_PATH}", logs.to_str().expect("non-utf8 logs path"))
-        .replace(
-            "{METRICS_PATH}",
-            metrics.to_str().expect("non-utf8 metrics path"),
-        )
-        .replace("{IDh}", format!("{id:02X}").as_str());
-
-    let config_path = PathBuf::from(format!("/tmp/{}-{id:02X}.json", args.bench));
-    fs::write(&config_path, config.as_bytes())
-        .await
-        .with_context(|| {
-            format!(
-                "ID={id} failed to write config to file {}",
-                config_path.display()
-            )
-        })?;
-    Ok(config_path)
+    rootfs.push(args.bench.name);
+    rootfs.push(format!("{}-{id:02X}.ext4", args.bench.name));
+    rootfs
 }
 
-/// Pause the uVM listening to socket `sock` using UDS client `ucli`.
-async fn pause_uvm(sock: impl AsRef<Path>, ucli: &Client<UnixConnector>) -> Result<()> {
-    const PAUSE_BODY: &str = r#"{"state":"Paused"}"#;
-
-    let uri = Uri::new(&sock, "/vm");
-    let req = Request::patch(uri)
-        .header(ACCEPT, APPLICATION_JSON)
-        .header(CONTENT_TYPE, APPLICATION_JSON)
-        .body(PAUSE_BODY.into())
-        .with_context(|| {
-            format!(
-                "failed to construct HTTP PATCH request for '{}' to pause the uVM",
-                sock.as_ref().display()
-            )
-        })?;
-
-    let resp = ucli.request(req).await.with_context(|| {
-        format!(
-            "failed to  HTTP PATCH /vm  @  '{}'",
-            sock.as_ref().display()
-        )
-    })?;
-
-    match resp.status() {
-        StatusCode::NO_CONTENT => Ok(()),
-        code => Err(anyhow!(
-            "Endpoint '{}' responded with {code}",
-            sock.as_ref().display()
-        )),
-    }
-}
+async fn render_config(id: u64, args: &Cmd) -> Result<PathBuf> {
+    let (logs, metrics) = truncate_files(id, args.bench.name, &args.store_path)
+        .await
+        .with_context(|| "ID={id} failed to truncate logs & metrics files")?;
 
-/// Resume the uVM listening to socket `sock` using UDS client `ucli`.
-async fn resume_uvm(sock: impl AsRef<Path>, ucli: &Client<UnixConnector>) -> Result<()> {
-    const RESUME_BODY: &str = r#"{"state":"Resumed"}"#;
-
-    let uri = Uri::new(&sock, "/vm");
-    let req = Request::patch(uri)
-        .header(ACCEPT, APPLICATION_JSON)
-        .header(CONTENT_TYPE, APPLICATION_JSON)
-        .body(RESUME_BODY.into())
-        .with_context(|| {
-            format!(
-                "failed to construct HTTP PATCH request for '{}' to pause the uVM",
-                sock.as_ref().display()
-            )
-        })?;
-
-    let resp = ucli.request(req).await.with_context(|| {
+    let rootfs = rootfs_path(id, args);
+    let idh = format!("{id:02X}");
+    let config = VmConfig {
+        boot_source: BootSource {
+            kernel_image_path: args.kernel_image_path.clone(),
+            boot_args: Some(BOOT_ARGS.to_owned()),
+            initrd_path: None,
+        },
+        drives: vec![Drive {
+            drive_id: "rootfs".to_owned(),
+            path_on_host: rootfs,
+            is_root_device: true,
+            is_read_only: true,
+            partuuid: None,
+        }],
+        machine_config: MachineConfig {
+            vcpu_count: args.vcpu_count,
+            mem_size_mib: args.vm_mem.unwrap_or(args.bench.mem_size_mib),
+            smt: false,
+            track_dirty_pages: args.diff_snapshots > 0,
+        },
+        logger: Some(Logger {
+            log_path: logs,
+            level: Some(LogLevel::Warning),
+            show_level: true,
+            show_log_origin: true,
+        }),
+        metrics: Some(Metrics {
+            metrics_path: metrics,
+        }),
+        network_interfaces: vec![NetworkInterface {
+            iface_id: "eth0".to_owned(),
+            guest_mac: Some(format!("AA:FC:00:00:05:{idh}")),
+            host_dev_name: format!("fcpmem01.{idh}"),
+        }],
+    };
+    let config = serde_json::to_vec_pretty(&config)
+        .with_context(|| format!("ID={id} failed to serialize uVM's configuration"))?;
+
+    let config_path = PathBuf::from(format!("/tmp/{}-{id:02X}.json", args.bench.name));
+    fs::write(&config_path, config).await.with_context(|| {
         format!(
-            "failed to  HTTP PATCH /vm  @  '{}'",
-            sock.as_ref().display()
+            "ID={id} failed to write config to file {}",
+            config_path.display()
         )
     })?;
-
-    match resp.status() {
-        StatusCode::NO_CONTENT => Ok(()),
-        code => Err(anyhow!(
-            "Endpoint '{}' responded with {code}",
-            sock.as_ref().display()
-        )),
-    }
+    Ok(config_path)
 }
 
-/// Create a snapshot for the uVM listening to socket `sock` using UDS client `ucli`.
-async fn create_snapshot(
+/// Describe the snapshot of uVM `id` in `dir`, created at `created`, for the manifest of `dir`;
+/// `layer` is whether it is a diff snapshot.
+async fn describe(
     id: u64,
     args: &Cmd,
-    sock: impl AsRef<Path>,
-    ucli: &Client<UnixConnector>,
-) -> Result<()> {
+    dir: &Path,
+    created: SystemTime,
+    layer: bool,
+) -> Result<SnapshotEntry> {
+    let state = dir.join(format!("snapshot-{id:02X}.file"));
+    let memory = dir.join(format!("memory-{id:02X}.file"));
+    let layer = layer.then(|| dir.join(diff::layer_name(id as usize)));
+    let (state, memory, layer) = tokio::task::spawn_blocking(move || {
+        Ok::<_, ManifestError>((
+            FileEntry::of(&state)?,
+            FileEntry::of(&memory)?,
+            layer.as_deref().map(FileEntry::of).transpose()?,
+        ))
+    })
+    .await??;
+    Ok(SnapshotEntry {
+        id: id as usize,
+        rootfs: rootfs_path(id, args),
+        created_unix_ms: created.duration_since(UNIX_EPOCH)?.as_millis() as u64,
+        state,
+        memory,
+        layer,
+    })
+}
+
+/// Create a full snapshot for the uVM behind `api`.
+async fn create_snapshot(id: u64, args: &Cmd, api: &FirecrackerApi) -> Result<SnapshotEntry> {
     let mut sp = args.store_path.to_path_buf();
     sp.push(format!("snapshot-{id:02X}.file"));
     let mut mp = args.store_path.to_path_buf();
     mp.push(format!("memory-{id:02X}.file"));
 
-    let restore_body = format!(
-        r#"{{"snapshot_path":"{}","mem_file_path":"{}","snapshot_type":"Full"}}"#,
-        sp.display(),
-        mp.display()
-    );
+    let created = SystemTime::now();
+    api.create_snapshot(&SnapshotCreateParams {
+        snapshot_type: SnapshotType::Full,
+        snapshot_path: sp,
+        mem_file_path: mp,
+    })
+    .await?;
+    describe(id, args, &args.store_path, created, false).await
+}
 
-    let uri = Uri::new(&sock, "/snapshot/create");
-    let req = Request::put(uri)
-        .header(ACCEPT, APPLICATION_JSON)
-        .header(CONTENT_TYPE, APPLICATION_JSON)
-        .body(restore_body.into())
-        .with_context(|| {
-            format!(
-                "failed to construct HTTP PUT request for '{}' to snapshot the uVM",
-                sock.as_ref().display()
-            )
-        })?;
-
-    let resp = ucli.request(req).await.with_context(|| {
-        format!(
-            "failed to  HTTP PUT /snapshot/create  @  '{}'",
-            sock.as_ref().display()
-        )
-    })?;
+/// Create the `k`-th diff snapshot for the uVM behind `api` under `$STORE/diffK/`, and layer its
+/// memory file on top of the previous snapshot's one, so that it can be restored from.
+async fn create_diff_snapshot(
+    id: u64,
+    k: usize,
+    args: &Cmd,
+    api: &FirecrackerApi,
+) -> Result<SnapshotEntry> {
+    let dir = args.store_path.join(format!("diff{k}"));
+    let prev = match k {
+        1 => args.store_path.clone(),
+        k => args.store_path.join(format!("diff{}", k - 1)),
+    };
+    let memory = format!("memory-{id:02X}.file");
+    let layer = dir.join(diff::layer_name(id as usize));
+
+    let created = SystemTime::now();
+    api.create_snapshot(&SnapshotCreateParams {
+        snapshot_type: SnapshotType::Diff,
+        snapshot_path: dir.join(format!("snapshot-{id:02X}.file")),
+        mem_file_path: layer.clone(),
+    })
+    .await?;
+
+    let merged = dir.join(&memory);
+    fs::copy(prev.join(&memory), &merged)
+        .await
+        .with_context(|| format!("failed to copy '{}'", prev.join(&memory).display()))?;
+    tokio::task::spawn_blocking(move || diff::rebase(&merged, &layer)).await??;
+    describe(id, args, &dir, created, true).await
+}
 
-    match resp.status() {
-        StatusCode::NO_CONTENT => Ok(()),
-        code => Err(anyhow!(
-            "Endpoint '{}' responded with {code}",
-            sock.as_ref().display()
-        )),
+/// Issue `n` requests with the given `rpc_args` to the uVM at `address_port`, one after the other.
+async fn warm_up(address_port: &str, rpc_args: &RpcArgs, n: usize) -> Result<()> {
+    let client = BenchClient::new(format!("http://{address_port}"))?;
+    for _ in 0..n {
+        client.bench(rpc_args).await?;
     }
+    Ok(())
 }
 
 async fn snapshot_task(
     id: u64,
     args: Cmd,
-    ucli: Client<UnixConnector>,
+    rpc_args: RpcArgs,
     mut rng: StdRng,
     pb: Arc<ProgressBar>,
-) -> Result<()> {
+) -> Result<Vec<SnapshotEntry>> {
     let address_port = VM_ADDR_FMT.replace("ID", id.to_string().as_str());
 
     // Create the path to the UDS and remove any present socket
-    let sock = PathBuf::from(format!("/tmp/firecracker-{}-{id:02X}.socket", args.bench));
+    let sock = PathBuf::from(format!(
+        "/tmp/firecracker-{}-{id:02X}.socket",
+        args.bench.name
+    ));
     if fs::metadata(sock.as_path()).await.is_ok() {
         fs::remove_file(sock.as_path())
             .await
@@ -310,7 +334,7 @@ async fn snapshot_task(
     // Spawn the uVM (replace all "_" in benchmark's name with "-" to be a valid Firecracker id)
     let mut fc = Command::new(&args.fc_bin)
         .arg("--id")
-        .arg(format!("{}-{id:02X}", args.bench.replace('_', "-")))
+        .arg(format!("{}-{id:02X}", args.bench.cli_name()))
         .arg("--config-file")
         .arg(&config_path)
         .arg("--api-sock")
@@ -325,21 +349,46 @@ async fn snapshot_task(
         .await
         .with_context(|| format!("ID={id} failed to connect to the gRPC server"))?;
 
+    // Warm it up, if asked to
+    warm_up(&address_port, &rpc_args, args.warm_invocations)
+        .await
+        .with_context(|| format!("ID={id} failed to warm up uVM"))?;
+
     // ¿FIXME?(ckatsak): Wait some more (with jitter)?
     sleep(Duration::from_millis(rng.gen_range(300..750))).await;
 
     // Pause it
-    pause_uvm(&sock, &ucli)
+    let api = FirecrackerApi::new(&sock);
+    api.pause()
         .await
         .with_context(|| format!("ID={id} failed to pause uVM"))?;
 
     // Create a snapshot from it
-    create_snapshot(id, &args, &sock, &ucli)
+    let mut entries = Vec::with_capacity(args.diff_snapshots + 1);
+    let entry = create_snapshot(id, &args, &api)
         .await
         .with_context(|| format!("ID={id} failed to create snapshot for uVM"))?;
+    entries.push(entry);
+
+    // Warm it up and create each of its diff snapshots
+    for k in 1..=args.diff_snapshots {
+        api.resume()
+            .await
+            .with_context(|| format!("ID={id} failed to resume uVM"))?;
+        warm_up(&address_port, &rpc_args, 1)
+            .await
+            .with_context(|| format!("ID={id} failed to warm up uVM"))?;
+        api.pause()
+            .await
+            .with_context(|| format!("ID={id} failed to pause uVM"))?;
+        let entry = create_diff_snapshot(id, k, &args, &api)
+            .await
+            .with_context(|| format!("ID={id} failed to create diff snapshot #{k} for uVM"))?;
+        entries.push(entry);
+    }
 
     // Resume it and poll the gRPC server inside it again
-    resume_uvm(&sock, &ucli)
+    api.resume()
         .await
         .with_context(|| format!("ID={id} failed to resume uVM"))?;
     wait_port(&address_port, &mut rng)
@@ -357,7 +406,26 @@ async fn snapshot_task(
             .await
             .with_context(|| "ID={id} failed to cleanup uVM's config & socket")?;
     }
-    Ok(())
+    Ok(entries)
+}
+
+/// The version of the Firecracker binary at `fc_bin`, as reported by `firecracker --version`.
+async fn fc_version(fc_bin: &Path) -> Result<String> {
+    let out = Command::new(fc_bin)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .with_context(|| format!("failed to run '{} --version'", fc_bin.display()))?;
+    if !out.status.success() {
+        bail!("'{} --version' failed ({})", fc_bin.display(), out.status);
+    }
+    Ok(String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_owned())
 }
 
 #[tokio::main]
@@ -365,6 +433,25 @@ async fn main() -> Result<()> {
     let _ = dotenv::from_filename("config")
         .with_context(|| r#"failed to read environment variables from parents' "config" file"#)?;
     let cmd = Cmd::parse();
+    if !cmd.bench_args.is_empty() && cmd.warm_invocations == 0 && cmd.diff_snapshots == 0 {
+        bail!("'--bench-args' requires either '--warm-invocations' or '--diff-snapshots'");
+    }
+    let rpc_args = if cmd.bench_args.is_empty() {
+        cmd.bench.default_rpc_args()
+    } else {
+        cmd.bench.rpc_args(&cmd.bench_args)?
+    };
+    if cmd.bench.needs_minio {
+        eprintln!(
+            "WARNING: '{}' fetches its input from MinIO, which rejects requests from clients \
+             with >{}min clock skew; its snapshots will only be usable for a while (as recorded \
+             in their manifest).",
+            cmd.bench.name,
+            MINIO_MAX_SKEW.as_secs() / 60
+        );
+    }
+
+    let firecracker_version = fc_version(&cmd.fc_bin).await?;
 
     let pb = ProgressBar::new(cmd.num_uvms);
     pb.set_style(
@@ -375,12 +462,18 @@ async fn main() -> Result<()> {
     let pb = Arc::new(pb);
 
     create_dirs(&cmd.store_path).await?;
+    for k in 1..=cmd.diff_snapshots {
+        fs::create_dir_all(cmd.store_path.join(format!("diff{k}")))
+            .await
+            .with_context(|| "failed to create directory tree for diff snapshots")?;
+    }
 
-    future::try_join_all((0..cmd.num_uvms).map(|id| {
+    let entries = future::try_join_all((0..cmd.num_uvms).map(|id| {
         let args = cmd.clone();
+        let rpc_args = rpc_args.clone();
         let rng = SeedableRng::from_entropy();
         let pb = pb.clone();
-        tokio::spawn(async move { snapshot_task(id, args, Client::unix(), rng, pb).await })
+        tokio::spawn(async move { snapshot_task(id, args, rpc_args, rng, pb).await })
     }))
     .await
     .with_context(|| "failed to join worker tasks")?
@@ -390,44 +483,31 @@ async fn main() -> Result<()> {
 
     pb.finish_with_message("snapshots are ready!");
 
+    // Describe the full snapshots, as well as each level of diff snapshots, in their directories
+    for k in 0..=cmd.diff_snapshots {
+        let (dir, diff) = match k {
+            0 => (cmd.store_path.clone(), None),
+            k => (cmd.store_path.join(format!("diff{k}")), Some(k)),
+        };
+        Manifest {
+            bench: cmd.bench.name.to_owned(),
+            mem_size_mib: cmd.vm_mem.unwrap_or(cmd.bench.mem_size_mib),
+            vcpu_count: cmd.vcpu_count,
+            kernel_image: cmd.kernel_image_path.clone(),
+            firecracker: cmd.fc_bin.clone(),
+            firecracker_version: firecracker_version.clone(),
+            warm_invocations: cmd.warm_invocations,
+            bench_args: cmd.bench_args.clone(),
+            diff,
+            snapshots: entries.iter().map(|e| e[k].clone()).collect(),
+        }
+        .save(&dir)?;
+    }
+
     if cmd.cleanup {
         cleanup_dirs(&cmd.store_path).await?;
     }
     Ok(())
 }
 
-const MICROVM_CONFIG_TEMPLATE: &str = r##"{
-    "boot-source": {
-        "kernel_image_path": "{KERNEL_IMG_PATH}",
-        "boot_args": "8250.nr_uarts=0 reboot=k panic=1 pci=off ro noapic nomodules random.trust_cpu=on transparent_hugepage=always"
-    },
-    "drives": [
-        {
-            "drive_id": "rootfs",
-            "path_on_host": "{ROOTFS_PATH_ON_HOST}",
-            "is_root_device": true,
-            "is_read_only": true
-        }
-    ],
-    "machine-config": {
-        "mem_size_mib": {VM_MEM_MB},
-        "vcpu_count": {VCPU_COUNT},
-        "smt": false
-    },
-    "logger": {
-        "log_path": "{LOG_PATH}",
-        "level": "Warning",
-        "show_level": true,
-        "show_log_origin": true
-    },
-    "metrics": {
-        "metrics_path": "{METRICS_PATH}"
-    },
-    "network-interfaces": [
-        {
-            "iface_id": "eth0",
-            "guest_mac": "AA:FC:00:00:05:{IDh}",
-            "host_dev_name": "fcpmem01.{IDh}"
-        }
-    ]
-}"##;
+const BOOT_ARGS: &str = "8250.nr_uarts=0 reboot=k panic=1 pci=off ro noapic nomodules random.trust_cpu=on transparent_hugepage=always";